@@ -3,11 +3,23 @@ extern crate clap;
 extern crate warkov;
 use clap::Parser;
 
+use std::fs::File;
+use std::io::{self, BufRead, Read};
 use std::path::PathBuf;
 
 use anyhow::Result;
 
-use warkov::MarkovChain;
+use warkov::{capitalize, Compression, MarkovChain};
+
+/// How the input file is compressed.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum CompressionArg {
+    /// Going by the file extension: `.gz` is gzip, `.zst` is zstd, anything else isn't compressed
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+}
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -29,33 +41,113 @@ struct Args {
     /// `max-look` inclusive.
     min_look: Option<usize>,
 
+    #[clap(long, value_name = "N")]
+    /// Generate reproducible words. Each word only depends on the seed, the input file, the
+    /// lookbehind used and its position in the output, so `--num` doesn't change the earlier words.
+    seed: Option<u64>,
+
+    #[clap(long)]
+    /// Make the first letter of each word upper case, e.g. for names
+    capitalize: bool,
+
+    #[clap(long, value_enum, default_value = "auto", value_name = "COMPRESSION")]
+    /// How the input file is compressed
+    compression: CompressionArg,
+
     #[clap(parse(from_os_str))]
     /// Filename to read example words from, e.g. /usr/share/dict/words
     filename: PathBuf,
 }
 
-fn generate(markov: &mut MarkovChain<char, impl warkov::Rng>, len: usize) -> String {
-    markov.generate_max_look(len).into_iter().collect()
+/// The seed for the `index`th word generated with lookbehind `look`.
+///
+/// This is the 64 bit FNV-1a hash of all three, like `MarkovChain::generate_from_key`, so the
+/// words for one seed don't turn up again for other seeds.
+fn word_seed(seed: u64, look: usize, index: usize) -> u64 {
+    [seed, look as u64, index as u64]
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Reads the lines of a reader lower cased.
+struct Lowercase<R> {
+    inner: R,
+    line: String,
+    pos: usize,
+}
+
+impl<R: BufRead> Read for Lowercase<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Lowercase<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            self.line = line.to_lowercase();
+            self.pos = 0;
+        }
+        Ok(&self.line.as_bytes()[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let file = std::fs::read_to_string(args.filename)?;
+    let compression = match args.compression {
+        CompressionArg::Auto => Compression::from_path(&args.filename),
+        CompressionArg::None => Compression::None,
+        CompressionArg::Gzip => Compression::Gzip,
+        CompressionArg::Zstd => Compression::Zstd,
+    };
     let mut markov = MarkovChain::new(args.max_look);
-    for line in file.lines() {
-        markov.train(line.to_lowercase().chars());
-    }
+    markov.train_from_reader(Lowercase {
+        inner: compression.decompress(File::open(&args.filename)?)?,
+        line: String::new(),
+        pos: 0,
+    })?;
+
+    let mut generate = |look: usize, index: usize| -> String {
+        match args.seed {
+            None if args.capitalize => markov.generate_capitalized(look),
+            None => markov.generate_string(look),
+            Some(seed) => {
+                let word: String = markov
+                    .nth(word_seed(seed, look, index), look)
+                    .into_iter()
+                    .collect();
+                if args.capitalize {
+                    capitalize(&word)
+                } else {
+                    word
+                }
+            }
+        }
+    };
 
     match args.min_look {
         None => {
-            for _ in 0..args.num {
-                println!("{}", generate(&mut markov, args.max_look))
+            for index in 0..args.num {
+                println!("{}", generate(args.max_look, index))
             }
         }
         Some(min_look) => {
             for len in (min_look..=args.max_look).rev() {
-                for _ in 0..args.num {
-                    println!("{} {}", len, generate(&mut markov, len))
+                for index in 0..args.num {
+                    println!("{} {}", len, generate(len, index))
                 }
             }
         }
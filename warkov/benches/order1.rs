@@ -0,0 +1,47 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use warkov::MarkovChain;
+
+/// Random words, with a bias towards some letters, so the chain has something to learn.
+fn words() -> Vec<String> {
+    let mut rng = XorShiftRng::from_seed([1; 16]);
+    (0..5_000)
+        .map(|_| {
+            let len = rng.gen_range(3, 12);
+            (0..len)
+                .map(|_| {
+                    let n: u8 = rng.gen_range(0, 26);
+                    (b'a' + (n * n) % 26) as char
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn trained(size: usize, words: &[String]) -> MarkovChain<char, XorShiftRng> {
+    let mut mc = MarkovChain::new_with_rng(size, XorShiftRng::from_seed([2; 16]));
+    for word in words.iter() {
+        mc.train(word.chars());
+    }
+    mc
+}
+
+/// The fast path for a lookbehind of 1, against the general path, which a size 1 chain would
+/// otherwise use, and a size 3 chain for comparison.
+fn generate(c: &mut Criterion) {
+    let words = words();
+    let mut group = c.benchmark_group("generate");
+    let mut mc = trained(1, &words);
+    group.bench_function("size 1 fast path", |b| b.iter(|| mc.generate_max_look(1)));
+    let mut mc = trained(1, &words);
+    group.bench_function("size 1 general path", |b| {
+        b.iter(|| mc.generate_general_path(1))
+    });
+    let mut mc = trained(3, &words);
+    group.bench_function("size 3", |b| b.iter(|| mc.generate_max_look(3)));
+    group.finish();
+}
+
+criterion_group!(benches, generate);
+criterion_main!(benches);
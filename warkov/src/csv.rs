@@ -0,0 +1,338 @@
+//! Reading and writing chains as CSV.
+//!
+//! Each row is one next item of one stage, with the columns `context,next,count`. `context` is
+//! the items of the stage separated by `|`, where an empty item is the start of the term. An empty
+//! `next` is the end of the term. Items which are empty, or contain `|`, `"` or a newline, are
+//! quoted with `"`, as are CSV fields which contain `,`, `"` or a newline. Quotes inside a quoted
+//! item or field are doubled.
+//!
+//! There's no version number. The header stands in for one: if the columns ever change, so does
+//! the header, and `read_csv` rejects CSV with a header it doesn't know.
+
+use super::{Boundary, Error, MarkovChain};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+const HEADER: [&str; 3] = ["context", "next", "count"];
+const ITEM_SEPARATOR: char = '|';
+
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug + Display,
+{
+    /// Writes the stages of this chain as CSV, sorted by stage.
+    ///
+    /// The rows are `context,next,count`, where `context` is the items of the stage separated by
+    /// `|`, and an empty item or `next` is the start or end of the term.
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "{}", HEADER.join(","))?;
+
+        let mut stages: Vec<_> = self.stages.iter().collect();
+        stages.sort();
+        for (stage, stats) in stages {
+            let context = stage
+                .iter()
+                .map(|item| match item {
+                    Boundary::Tok(t) => quote(&t.to_string(), ITEM_SEPARATOR, true),
+                    _ => String::new(),
+                })
+                .collect::<Vec<String>>()
+                .join(&ITEM_SEPARATOR.to_string());
+
+            for (next, count) in stats.1.iter() {
+                let next = match next {
+                    Boundary::Tok(t) => quote(&t.to_string(), ',', true),
+                    _ => String::new(),
+                };
+                writeln!(w, "{},{},{}", quote(&context, ',', false), next, count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> MarkovChain<T, ()>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug + FromStr,
+{
+    /// Reads a chain with a max look a head of `size` from CSV written by `write_csv`.
+    ///
+    /// The totals are recalculated from the counts. The alphabet isn't stored in the CSV, so it's
+    /// recalculated from the stages of length `size`, and the shorter stages at the start of the
+    /// term, which is exact for chains trained with `train`.
+    /// Returns an `InvalidData` error if the CSV is malformed, a stage is longer than `size`, or
+    /// the start of the term is anywhere but the front of the stage, and an `InvalidInput` error
+    /// if `size` is 0.
+    pub fn read_csv<Rd: Read>(size: usize, mut r: Rd) -> io::Result<Self> {
+        if size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, Error::ZeroSize));
+        }
+        let mut input = String::new();
+        r.read_to_string(&mut input)?;
+
+        let mut mc: Self = MarkovChain::without_rng(size);
+        let mut records = parse(&input, ',', true).map_err(invalid_data)?.into_iter();
+        match records.next() {
+            Some(header) if header.iter().map(|f| f.text.as_str()).eq(HEADER) => {}
+            _ => return Err(invalid_data("missing header")),
+        }
+
+        for (row, record) in records.enumerate() {
+            let row = row + 2;
+            let invalid = |msg: &str| invalid_data(format!("row {}: {}", row, msg));
+            if record.len() != HEADER.len() {
+                return Err(invalid("expected 3 fields"));
+            }
+
+            let items = parse(&record[0].text, ITEM_SEPARATOR, false)
+                .map_err(|e| invalid(&e))?
+                .pop()
+                .unwrap_or_default();
+            let mut stage = Vec::with_capacity(items.len());
+            for (idx, item) in items.into_iter().enumerate() {
+                if item.is_boundary() {
+                    if idx != 0 {
+                        return Err(invalid("the start can only be first in the context"));
+                    }
+                    stage.push(Boundary::Start);
+                } else {
+                    stage.push(Boundary::Tok(
+                        parse_item(&item.text).ok_or_else(|| invalid("invalid item"))?,
+                    ));
+                }
+            }
+            if stage.is_empty() || stage.len() > size {
+                return Err(invalid("context has the wrong length"));
+            }
+
+            let next = if record[1].is_boundary() {
+                Boundary::End
+            } else {
+                Boundary::Tok(parse_item(&record[1].text).ok_or_else(|| invalid("invalid item"))?)
+            };
+            let count: usize = match record[2].text.parse() {
+                Ok(count) if count > 0 => count,
+                _ => return Err(invalid("count must be a positive integer")),
+            };
+
+            let stats = mc.stages.entry(stage).or_default();
+            if stats.1.insert(next, count).is_some() {
+                return Err(invalid("duplicate row"));
+            }
+            stats.0 += count;
+        }
+
+        // When training, each item is counted once in a stage of length `size`, or in a shorter
+        // stage at the start of the term.
+        let mut alphabet: BTreeMap<T, usize> = BTreeMap::new();
+        for (_, stats) in mc
+            .stages
+            .iter()
+            .filter(|(stage, _)| stage.len() == size || stage[0] == Boundary::Start)
+        {
+            for (next, count) in stats.1.iter() {
+                if let Boundary::Tok(t) = next {
+                    *alphabet.entry(t.clone()).or_default() += count;
+                }
+            }
+        }
+        mc.alphabet = (alphabet.values().sum(), alphabet);
+
+        Ok(mc)
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn parse_item<T: FromStr>(text: &str) -> Option<T> {
+    text.parse().ok()
+}
+
+/// `text`, quoted if it contains `delim`, a quote or a newline, or if `quote_empty` and it's empty.
+fn quote(text: &str, delim: char, quote_empty: bool) -> String {
+    if (quote_empty && text.is_empty()) || text.contains([delim, '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+impl Field {
+    /// An empty field which wasn't quoted, meaning the start or end of the term.
+    fn is_boundary(&self) -> bool {
+        self.text.is_empty() && !self.quoted
+    }
+}
+
+/// Splits `input` into records of fields separated by `delim`. If `split_lines`, each line is
+/// a record, otherwise it's all one record. Trailing empty lines are ignored.
+fn parse(input: &str, delim: char, split_lines: bool) -> Result<Vec<Vec<Field>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = Field::default();
+    let mut chars = input.chars().peekable();
+    let mut in_quotes = false;
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.text.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.text.push(c);
+            }
+        } else if c == '"' {
+            if field.quoted || !field.text.is_empty() {
+                return Err("unexpected quote".to_string());
+            }
+            field.quoted = true;
+            in_quotes = true;
+        } else if field.quoted && c != delim && !(split_lines && (c == '\n' || c == '\r')) {
+            return Err("unexpected text after a quote".to_string());
+        } else if c == delim {
+            record.push(std::mem::take(&mut field));
+        } else if split_lines && c == '\r' && chars.peek() == Some(&'\n') {
+            continue;
+        } else if split_lines && c == '\n' {
+            record.push(std::mem::take(&mut field));
+            records.push(std::mem::take(&mut record));
+        } else {
+            field.text.push(c);
+        }
+    }
+
+    if in_quotes {
+        return Err("unterminated quote".to_string());
+    }
+    if !split_lines || field.quoted || !field.text.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T>(mc: &MarkovChain<T, ()>) -> MarkovChain<T, ()>
+    where
+        T: Hash + Eq + Clone + Default + Ord + Debug + Display + FromStr,
+    {
+        let mut csv = Vec::new();
+        mc.write_csv(&mut csv).unwrap();
+        MarkovChain::read_csv(mc.size, csv.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn write_csv1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("ab".chars());
+        mc.train("a".chars());
+
+        let mut csv = Vec::new();
+        mc.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "context,next,count\n\
+             ,a,2\n\
+             |a,,1\n\
+             |a,b,1\n\
+             a,,2\n\
+             a,b,2\n\
+             a|b,,1\n\
+             b,,2\n"
+        );
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut mc = MarkovChain::without_rng(3);
+        for word in ["abc", "a|b", "a,b", "\"quoted\"", "new\nline", ""] {
+            mc.train(word.chars());
+        }
+        let read = round_trip(&mc);
+        assert_eq!(read.stages, mc.stages);
+        assert_eq!(read.alphabet, mc.alphabet);
+
+        // Empty strings are items, not boundaries
+        let mut mc: MarkovChain<String, ()> = MarkovChain::without_rng(2);
+        mc.train(vec![String::new(), "x".to_string(), String::new()].into_iter());
+        let read = round_trip(&mc);
+        assert_eq!(read.stages, mc.stages);
+        assert_eq!(read.alphabet, mc.alphabet);
+    }
+
+    #[test]
+    fn read_csv_invalid() {
+        let read = |csv: &str| MarkovChain::<char, ()>::read_csv(2, csv.as_bytes());
+        assert!(read("context,next,count\n,a,1\n").is_ok());
+        assert!(read("").is_err());
+        assert!(MarkovChain::<char, ()>::read_csv(0, "context,next,count\n".as_bytes()).is_err());
+        assert!(read("context,next,count\n,a\n").is_err());
+        assert!(read("context,next,count\n,a,0\n").is_err());
+        assert!(read("context,next,count\n,a,x\n").is_err());
+        assert!(read("context,next,count\na|b|c,a,1\n").is_err());
+        assert!(read("context,next,count\na|,a,1\n").is_err());
+        assert!(read("context,next,count\na,ab,1\n").is_err());
+        assert!(read("context,next,count\na,b,1\na,b,2\n").is_err());
+        assert!(read("context,next,count\n\"a,b,1\n").is_err());
+
+        let mc = read("context,next,count\r\na,b,1\r\na,,2\r\n").unwrap();
+        assert_eq!(mc.stages[&vec![Boundary::Tok('a')]].0, 3);
+    }
+
+    #[test]
+    fn parse1() {
+        let fields = |input: &str| -> Vec<Vec<String>> {
+            parse(input, ',', true)
+                .unwrap()
+                .into_iter()
+                .map(|record| record.into_iter().map(|f| f.text).collect())
+                .collect()
+        };
+        assert_eq!(fields("a,b\nc,d\n"), vec![vec!["a", "b"], vec!["c", "d"]]);
+        assert_eq!(fields("\"a,\"\"b\",c"), vec![vec!["a,\"b", "c"]]);
+        assert_eq!(fields("\"a\nb\",\n"), vec![vec!["a\nb", ""]]);
+        assert!(parse("\"a\"b", ',', true).is_err());
+        assert!(parse("a\"b\"", ',', true).is_err());
+
+        assert_eq!(
+            parse("", '|', false).unwrap(),
+            vec![vec![Field {
+                text: String::new(),
+                quoted: false
+            }]]
+        );
+        assert_eq!(
+            parse("\"\"|", '|', false).unwrap(),
+            vec![vec![
+                Field {
+                    text: String::new(),
+                    quoted: true
+                },
+                Field {
+                    text: String::new(),
+                    quoted: false
+                }
+            ]]
+        );
+    }
+}
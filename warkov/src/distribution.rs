@@ -0,0 +1,78 @@
+//! Using a chain as a `rand` distribution.
+
+use super::{MarkovChain, Result};
+use rand::distributions::Distribution;
+use rand::Rng;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// A chain as a distribution of terms, for use with `Rng::sample` and `Rng::sample_iter`.
+///
+/// Made with [`MarkovChain::distribution`]. Each sample is a new term, the same as
+/// `generate_with_rng` with the lookbehind the distribution was made with.
+pub struct TermDistribution<'a, T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    chain: &'a MarkovChain<T, R>,
+    max_lookbehind: usize,
+}
+
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    /// This chain as a distribution of terms generated with a custom lookbehind.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn distribution(&self, max_lookbehind: usize) -> TermDistribution<'_, T, R> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        TermDistribution {
+            chain: self,
+            max_lookbehind,
+        }
+    }
+
+    /// Like `distribution`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_distribution(&self, max_lookbehind: usize) -> Result<TermDistribution<'_, T, R>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.distribution(max_lookbehind))
+    }
+}
+
+impl<T, R> Distribution<Vec<T>> for TermDistribution<'_, T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    fn sample<R2: Rng + ?Sized>(&self, rng: &mut R2) -> Vec<T> {
+        self.chain.generate_with_rng(rng, self.max_lookbehind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seeded_rng;
+
+    #[test]
+    fn distribution1() {
+        let mut mc = MarkovChain::without_rng(2);
+        for word in ["abc", "bbc", "acb"] {
+            mc.train(word.chars());
+        }
+
+        let dist = mc.distribution(2);
+        let mut rng = seeded_rng(3);
+        let sampled: Vec<Vec<char>> = rng.sample_iter(&dist).take(10).collect();
+        let mut rng = seeded_rng(3);
+        let generated: Vec<Vec<char>> =
+            (0..10).map(|_| mc.generate_with_rng(&mut rng, 2)).collect();
+        assert_eq!(sampled, generated);
+        assert!(mc.can_generate(&seeded_rng(4).sample(&dist)));
+
+        assert!(MarkovChain::<char, ()>::without_rng(2)
+            .try_distribution(2)
+            .is_err());
+    }
+}
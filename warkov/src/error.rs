@@ -0,0 +1,85 @@
+//! Errors from the `try_` methods.
+
+use std::fmt;
+
+/// Why a `try_` method failed. The method without `try_` panics instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A chain was made with a lookbehind of 0.
+    ZeroSize,
+    /// A lookbehind, or order, was 0 or greater than the lookbehind the chain was created with.
+    InvalidLookbehind {
+        /// The lookbehind which was asked for.
+        max_lookbehind: usize,
+        /// The lookbehind the chain was created with.
+        size: usize,
+    },
+    /// The chain hasn't been trained, so there's nothing to generate from. The methods without
+    /// `try_` generate empty terms instead of panicking.
+    Untrained,
+    /// `Boundary::Start` wasn't first in a term, or `Boundary::End` wasn't last.
+    MisplacedBoundary,
+    /// Two chains which need the same lookbehind have different ones.
+    SizeMismatch {
+        /// The lookbehind of this chain.
+        size: usize,
+        /// The lookbehind of the other chain.
+        other_size: usize,
+    },
+    /// Saved bytes are in a format which this version of warkov can't read, because they were
+    /// saved by a newer version.
+    UnsupportedVersion {
+        /// The format version of the bytes.
+        version: u32,
+        /// The newest format version which this version of warkov can read.
+        supported: u32,
+    },
+    /// The counts of a chain don't agree with each other, from `MarkovChain::validate`. The
+    /// message says how.
+    Inconsistent(&'static str),
+    /// Saved bytes aren't a chain, or are damaged, from `FrozenChain::from_bytes`. The message
+    /// says what's wrong.
+    Malformed(&'static str),
+    /// Some other argument was out of range, or the chain is too big for the method. The message
+    /// says which, and why.
+    InvalidArgument(&'static str),
+}
+
+/// The result of a `try_` method.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ZeroSize => write!(f, "the lookbehind must be more than 0"),
+            Error::InvalidLookbehind {
+                max_lookbehind,
+                size,
+            } => write!(
+                f,
+                "lookbehind {} must be between 1 and the lookbehind of the chain, {}",
+                max_lookbehind, size
+            ),
+            Error::Untrained => write!(f, "the chain hasn't been trained"),
+            Error::MisplacedBoundary => write!(
+                f,
+                "Boundary::Start must be first and Boundary::End must be last"
+            ),
+            Error::SizeMismatch { size, other_size } => write!(
+                f,
+                "the chains have different lookbehinds, {} and {}",
+                size, other_size
+            ),
+            Error::UnsupportedVersion { version, supported } => write!(
+                f,
+                "format version {} isn't supported, only versions up to {} are",
+                version, supported
+            ),
+            Error::Inconsistent(msg) => write!(f, "the chain is inconsistent: {}", msg),
+            Error::Malformed(msg) => write!(f, "not a valid frozen chain: {}", msg),
+            Error::InvalidArgument(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
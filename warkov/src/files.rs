@@ -0,0 +1,228 @@
+//! Training on text files.
+
+use super::MarkovChain;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// How a file of terms is compressed.
+///
+/// Decompressing needs the feature of the same name, `gzip` or `zstd`. Without it, decompressing
+/// returns an error of kind `io::ErrorKind::Unsupported`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Not compressed.
+    #[default]
+    None,
+    /// Compressed with gzip, e.g. a `.txt.gz` file.
+    Gzip,
+    /// Compressed with zstd, e.g. a `.txt.zst` file.
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of the file at `path`, going by its extension: `.gz` is gzip, `.zst` is
+    /// zstd, and anything else isn't compressed.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wraps `reader` in a reader of its decompressed contents, which can be given to
+    /// `MarkovChain::train_from_reader`.
+    pub fn decompress<'a>(self, reader: impl Read + 'a) -> io::Result<Box<dyn BufRead + 'a>> {
+        match self {
+            Compression::None => Ok(Box::new(BufReader::new(reader))),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+                reader,
+            )))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(BufReader::new(zstd::Decoder::new(reader)?))),
+            #[allow(unreachable_patterns)]
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("warkov was built without support for {:?}", self),
+            )),
+        }
+    }
+}
+
+impl<R> MarkovChain<char, R> {
+    /// Trains this chain on every line read from `reader`, one line at a time, so the whole
+    /// input is never in memory. Each line is a term, and empty lines are skipped.
+    ///
+    /// `reader` can be anything which reads lines, e.g. a decompressor wrapped in a
+    /// `BufReader`, to train on compressed word lists. Returns the first error from `reader`,
+    /// including for input which isn't UTF-8, after training on the lines before it.
+    pub fn train_from_reader(&mut self, reader: impl BufRead) -> io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            if !line.is_empty() {
+                self.train(line.chars());
+            }
+        }
+        Ok(())
+    }
+
+    /// Trains this chain on every line of the file at `path`, decompressing it with
+    /// `compression`, e.g. `Compression::from_path(path)`. Like `train_from_reader`, the file is
+    /// read one line at a time, and the lines before an error are still trained on.
+    pub fn train_from_file(&mut self, path: &Path, compression: Compression) -> io::Result<()> {
+        self.train_from_reader(compression.decompress(File::open(path)?)?)
+    }
+
+    /// Trains this chain on every line of every file in `dir`, and in the directories in it if
+    /// `recursive`. Each line is a term, and empty lines are skipped. Files are read in order of
+    /// their paths, so training is the same every time.
+    ///
+    /// A file which can't be read, e.g. because it isn't UTF-8, doesn't stop the others being
+    /// trained on. Instead, it's returned with its error, and nothing from it is trained on,
+    /// since each file is read completely before training on it, unlike `train_from_reader`.
+    /// Files are decompressed going by their extension, see `Compression::from_path`.
+    /// Returns an error, without training on anything, if `dir`, or one of the directories in it,
+    /// can't be listed.
+    pub fn train_from_dir(
+        &mut self,
+        dir: &Path,
+        recursive: bool,
+    ) -> io::Result<Vec<(PathBuf, io::Error)>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    if recursive {
+                        dirs.push(path);
+                    }
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+
+        let mut failures = Vec::new();
+        for path in files {
+            match read_decompressed(&path) {
+                Ok(text) => {
+                    for line in text.lines().filter(|line| !line.is_empty()) {
+                        self.train(line.chars());
+                    }
+                }
+                Err(e) => failures.push((path, e)),
+            }
+        }
+        Ok(failures)
+    }
+}
+
+/// The whole of the file at `path`, decompressed going by its extension.
+fn read_decompressed(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    Compression::from_path(path)
+        .decompress(File::open(path)?)?
+        .read_to_string(&mut text)?;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Boundary;
+
+    #[test]
+    fn train_from_reader1() {
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train_from_reader("ab\r\n\nac\n".as_bytes()).unwrap();
+        assert_eq!(mc.stages[[Boundary::Start].as_slice()].0, 2);
+        assert!(!mc.has_context(&['\r']));
+
+        let mut mc = MarkovChain::without_rng(1);
+        assert!(mc
+            .train_from_reader([b'a', b'\n', 0xff].as_slice())
+            .is_err());
+        assert!(mc.has_context(&['a']));
+    }
+
+    #[test]
+    fn train_from_dir1() {
+        let dir =
+            std::env::temp_dir().join(format!("warkov-train-from-dir-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "ab\n\nac\n").unwrap();
+        fs::write(dir.join("bad.txt"), [b'a', 0xff]).unwrap();
+        fs::write(dir.join("sub").join("b.txt"), "xy").unwrap();
+
+        let mut mc = MarkovChain::without_rng(1);
+        let failures = mc.train_from_dir(&dir, false).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, dir.join("bad.txt"));
+        assert_eq!(mc.stages[[Boundary::Start].as_slice()].0, 2);
+        assert!(!mc.has_context(&['x']));
+
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train_from_dir(&dir, true).unwrap();
+        assert!(mc.has_context(&['x']));
+
+        assert!(mc.train_from_dir(&dir.join("missing"), true).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compression_from_path1() {
+        let from_path = |path: &str| Compression::from_path(Path::new(path));
+        assert_eq!(from_path("words.txt.gz"), Compression::Gzip);
+        assert_eq!(from_path("words.zst"), Compression::Zstd);
+        assert_eq!(from_path("words.txt"), Compression::None);
+        assert_eq!(from_path("gz"), Compression::None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn train_from_gzip1() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"ab\n\nac\n").unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train_from_reader(Compression::Gzip.decompress(gzipped.as_slice()).unwrap())
+            .unwrap();
+        assert_eq!(mc.stages[[Boundary::Start].as_slice()].0, 2);
+        assert!(mc.has_context(&['a']));
+
+        let path = std::env::temp_dir().join(format!("warkov-gzip-{}.txt.gz", std::process::id()));
+        fs::write(&path, &gzipped).unwrap();
+        let mut from_file = MarkovChain::without_rng(1);
+        from_file
+            .train_from_file(&path, Compression::from_path(&path))
+            .unwrap();
+        assert_eq!(from_file.stages, mc.stages);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn train_from_zstd1() {
+        let compressed = zstd::encode_all(b"ab\n\nac\n".as_slice(), 0).unwrap();
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train_from_reader(Compression::Zstd.decompress(compressed.as_slice()).unwrap())
+            .unwrap();
+        assert_eq!(mc.stages[[Boundary::Start].as_slice()].0, 2);
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn train_from_gzip_unsupported1() {
+        let err = Compression::Gzip.decompress([].as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
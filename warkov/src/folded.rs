@@ -0,0 +1,114 @@
+//! Characters which are the same regardless of case and accents.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+
+/// A `char` which is equal to, and sorts and hashes the same as, every other `char` with the same
+/// letter when ignoring case and diacritics. e.g. `'A'`, `'a'` and `'á'` are all the same.
+///
+/// A `MarkovChain<FoldedChar, _>` shares what it learns between the variants of a letter. It
+/// still stores a real `char`, so generated items are the variant which was seen first in
+/// training, which can be turned back into a `char` with `char::from`, or `folded` for the plain
+/// lower case letter.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FoldedChar(pub char);
+
+impl FoldedChar {
+    /// The lower case letter without diacritics, e.g. `'a'` for `'Á'`.
+    pub fn folded(&self) -> char {
+        let mut folded = None;
+        for lower in self.0.to_lowercase() {
+            decompose_canonical(lower, |c| {
+                if folded.is_none() && !is_combining_mark(c) {
+                    folded = Some(c);
+                }
+            });
+        }
+        folded.unwrap_or(self.0)
+    }
+}
+
+impl From<char> for FoldedChar {
+    fn from(c: char) -> Self {
+        FoldedChar(c)
+    }
+}
+
+impl From<FoldedChar> for char {
+    fn from(c: FoldedChar) -> Self {
+        c.0
+    }
+}
+
+impl fmt::Display for FoldedChar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl PartialEq for FoldedChar {
+    fn eq(&self, other: &Self) -> bool {
+        self.folded() == other.folded()
+    }
+}
+
+impl Eq for FoldedChar {}
+
+impl PartialOrd for FoldedChar {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FoldedChar {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.folded().cmp(&other.folded())
+    }
+}
+
+impl Hash for FoldedChar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded().hash(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MarkovChain;
+
+    #[test]
+    fn folded1() {
+        assert_eq!(FoldedChar('a').folded(), 'a');
+        assert_eq!(FoldedChar('A').folded(), 'a');
+        assert_eq!(FoldedChar('á').folded(), 'a');
+        assert_eq!(FoldedChar('Ő').folded(), 'o');
+        assert_eq!(FoldedChar('ß').folded(), 'ß');
+        assert_eq!(FoldedChar('1').folded(), '1');
+        assert_eq!(FoldedChar('\u{301}').folded(), '\u{301}');
+
+        assert_eq!(FoldedChar('A'), FoldedChar::from('á'));
+        assert_ne!(FoldedChar('a'), FoldedChar('b'));
+        assert!(FoldedChar('Á') < FoldedChar('b'));
+        assert_eq!(char::from(FoldedChar('Á')), 'Á');
+    }
+
+    #[test]
+    fn folded_chain() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("Ábc".chars().map(FoldedChar));
+        mc.train("abd".chars().map(FoldedChar));
+        mc.train("ABE".chars().map(FoldedChar));
+
+        assert_eq!(mc.alphabet.1.len(), 5);
+        assert_eq!(mc.alphabet.1[&FoldedChar('a')], 3);
+        let word: String = mc
+            .most_probable_term(2, 10)
+            .into_iter()
+            .map(char::from)
+            .collect();
+        assert_eq!(word, "Ábc");
+    }
+}
@@ -0,0 +1,563 @@
+//! Read only chains stored in a flat block of bytes.
+//!
+//! Every number is a little-endian `u32`, so every value is at an offset which is a multiple of
+//! 4. The bytes are, in order:
+//!
+//! * A header of 7 numbers: the magic bytes `WKVC`, the format version, the lookbehind, the
+//!   number of items, the number of stages, the number of next items, and the total count of the
+//!   alphabet.
+//! * The items, sorted, as pairs of the item (see `FrozenItem`) and its count in the alphabet.
+//!   An item is referred to elsewhere by its index in this table plus 1.
+//! * The stages, sorted, each as `lookbehind` numbers for the stage, padded with `u32::MAX`,
+//!   then the index of its first next item, its number of next items, and its total count. In a
+//!   stage, 0 is the start of the term.
+//! * The next items of every stage, as pairs of the item and its count. Here 0 is the end of the
+//!   term.
+//!
+//! There are no pointers, only indexes into these tables, so the bytes can be used from
+//! anywhere, e.g. a memory mapped file.
+//!
+//! The header has the format version, `FORMAT_VERSION`, which goes up when the layout changes.
+//! Bytes with a newer version than this version of warkov writes give an
+//! `Error::UnsupportedVersion`, rather than being misread.
+
+use super::{Boundary, Error, MarkovChain, Result, Rng};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+const MAGIC: [u8; 4] = *b"WKVC";
+const HEADER_LEN: usize = 7;
+const PADDING: u32 = u32::MAX;
+
+/// Items which can be stored in a `FrozenChain`, as a `u32`.
+pub trait FrozenItem: Sized {
+    /// This item as a number.
+    fn to_u32(&self) -> u32;
+
+    /// The item stored as `n`, or `None` if no item is stored as `n`.
+    fn from_u32(n: u32) -> Option<Self>;
+}
+
+impl FrozenItem for char {
+    fn to_u32(&self) -> u32 {
+        u32::from(*self)
+    }
+
+    fn from_u32(n: u32) -> Option<Self> {
+        char::from_u32(n)
+    }
+}
+
+macro_rules! frozen_int {
+    ($($t:ty),*) => {
+        $(
+            impl FrozenItem for $t {
+                fn to_u32(&self) -> u32 {
+                    u32::from(*self)
+                }
+
+                fn from_u32(n: u32) -> Option<Self> {
+                    <$t>::try_from(n).ok()
+                }
+            }
+        )*
+    };
+}
+
+frozen_int!(u8, u16, u32);
+
+/// A read only chain, which generates straight from its bytes without loading them first.
+///
+/// Made with [`MarkovChain::freeze`], or from the bytes of one with `from_bytes`. Generating
+/// with the same random number generator gives the same terms as the chain it was made from.
+/// See the `frozen` module source for the layout of the bytes.
+#[derive(Debug, Clone)]
+pub struct FrozenChain<'a, T> {
+    bytes: Cow<'a, [u8]>,
+    size: usize,
+    num_items: usize,
+    num_stages: usize,
+    num_next: usize,
+    total: u32,
+    _item: PhantomData<T>,
+}
+
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug + FrozenItem,
+{
+    /// A read only copy of this chain, which can be saved as bytes.
+    ///
+    /// # Panics
+    /// If there are `u32::MAX` items or more, more than `u32::MAX` stages or next items, or a
+    /// count is more than `u32::MAX`.
+    pub fn freeze(&self) -> FrozenChain<'static, T> {
+        match self.try_freeze() {
+            Ok(frozen) => frozen,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like `freeze`, but returns an error if this chain is too big to freeze.
+    pub fn try_freeze(&self) -> Result<FrozenChain<'static, T>> {
+        let items: BTreeSet<&T> = self
+            .alphabet
+            .1
+            .keys()
+            .chain(self.stages.iter().flat_map(|(stage, stats)| {
+                stage.iter().chain(stats.1.keys()).filter_map(|b| match b {
+                    Boundary::Tok(t) => Some(t),
+                    _ => None,
+                })
+            }))
+            .collect();
+        // `PADDING` can't be an item
+        if items.len() >= PADDING as usize {
+            return Err(too_big());
+        }
+        let ids: BTreeMap<&T, u32> = items.iter().zip(1..).map(|(t, id)| (*t, id)).collect();
+        let id = |b: &Boundary<T>| match b {
+            Boundary::Start | Boundary::End => 0,
+            Boundary::Tok(t) => ids[t],
+        };
+
+        let mut stages: Vec<(Vec<u32>, _)> = self
+            .stages
+            .iter()
+            .map(|(stage, stats)| {
+                let mut key: Vec<u32> = stage.iter().map(id).collect();
+                key.resize(self.size, PADDING);
+                (key, stats)
+            })
+            .collect();
+        stages.sort_by(|a, b| a.0.cmp(&b.0));
+        let num_next: usize = stages.iter().map(|(_, stats)| stats.1.len()).sum();
+
+        let mut words = vec![
+            u32::from_le_bytes(MAGIC),
+            FrozenChain::<T>::FORMAT_VERSION,
+            to_u32(self.size)?,
+            to_u32(items.len())?,
+            to_u32(stages.len())?,
+            to_u32(num_next)?,
+            to_u32(self.alphabet.0)?,
+        ];
+        for item in items.iter() {
+            words.push(item.to_u32());
+            words.push(to_u32(self.alphabet.1.get(*item).copied().unwrap_or(0))?);
+        }
+        let mut first_next = 0;
+        for (key, stats) in stages.iter() {
+            words.extend_from_slice(key);
+            words.push(to_u32(first_next)?);
+            words.push(to_u32(stats.1.len())?);
+            words.push(to_u32(stats.0)?);
+            first_next += stats.1.len();
+        }
+        for (_, stats) in stages.iter() {
+            for (next, count) in stats.1.iter() {
+                words.push(id(next));
+                words.push(to_u32(*count)?);
+            }
+        }
+
+        Ok(FrozenChain {
+            bytes: Cow::Owned(words.iter().flat_map(|w| w.to_le_bytes()).collect()),
+            size: self.size,
+            num_items: items.len(),
+            num_stages: stages.len(),
+            num_next,
+            total: to_u32(self.alphabet.0)?,
+            _item: PhantomData,
+        })
+    }
+}
+
+fn to_u32(n: usize) -> Result<u32> {
+    u32::try_from(n).map_err(|_| too_big())
+}
+
+fn too_big() -> Error {
+    Error::InvalidArgument("the chain is too big to freeze")
+}
+
+impl<'a, T: FrozenItem> FrozenChain<'a, T> {
+    /// The version of the layout of the bytes which this version of warkov writes.
+    pub const FORMAT_VERSION: u32 = 1;
+
+    /// Uses `bytes` from `to_bytes` as a chain, without copying them.
+    ///
+    /// The bytes are checked once, which reads all of them, but allocates nothing. Returns
+    /// `Error::Malformed` if they aren't a valid frozen chain, or `Error::UnsupportedVersion` if
+    /// they're from a newer version of warkov.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        check_version(bytes)?;
+        Self::check(Cow::Borrowed(bytes))
+    }
+
+    fn check(bytes: Cow<'a, [u8]>) -> Result<Self> {
+        if !bytes.len().is_multiple_of(4) || bytes.len() < HEADER_LEN * 4 {
+            return Err(invalid("too short"));
+        }
+        let word = |i: usize| read(&bytes, i);
+        let (size, num_items, num_stages, num_next, total) =
+            (word(2), word(3), word(4), word(5), word(6));
+        let num_words = bytes.len() as u64 / 4;
+        let chain = FrozenChain {
+            bytes,
+            size: size as usize,
+            num_items: num_items as usize,
+            num_stages: num_stages as usize,
+            num_next: num_next as usize,
+            total,
+            _item: PhantomData,
+        };
+        if chain.size == 0 {
+            return Err(invalid("the lookbehind is 0"));
+        }
+        let len = (chain.size as u64 + 3) * chain.num_stages as u64
+            + 2 * (chain.num_items as u64 + chain.num_next as u64)
+            + HEADER_LEN as u64;
+        if len != num_words {
+            return Err(invalid("wrong length"));
+        }
+
+        let mut total = 0u64;
+        for idx in 0..chain.num_items {
+            let (item, count) = chain.item(idx);
+            T::from_u32(item).ok_or_else(|| invalid("invalid item"))?;
+            total += u64::from(count);
+        }
+        if total != u64::from(chain.total) {
+            return Err(invalid("the alphabet total is wrong"));
+        }
+
+        let num_items = chain.num_items as u32;
+        let mut first_next = 0;
+        for idx in 0..chain.num_stages {
+            let key = chain.stage_key(idx);
+            let stage_len = key.clone().take_while(|id| *id != PADDING).count();
+            if stage_len == 0
+                || key.clone().skip(stage_len).any(|id| id != PADDING)
+                || key.clone().take(stage_len).any(|id| id > num_items)
+                || key.clone().take(stage_len).skip(1).any(|id| id == 0)
+            {
+                return Err(invalid("invalid stage"));
+            }
+            if idx > 0 && chain.stage_key(idx - 1).cmp(key) != Ordering::Less {
+                return Err(invalid("the stages aren't sorted"));
+            }
+
+            let (first, len, stage_total) = chain.stage_next(idx);
+            if first != first_next || len == 0 || first + len > chain.num_next {
+                return Err(invalid("invalid next items"));
+            }
+            first_next += len;
+            let mut total = 0u64;
+            for next in first..first + len {
+                let (item, count) = chain.next(next);
+                if item > num_items {
+                    return Err(invalid("invalid next items"));
+                }
+                total += u64::from(count);
+            }
+            if total != u64::from(stage_total) {
+                return Err(invalid("a stage total is wrong"));
+            }
+        }
+        if first_next != chain.num_next {
+            return Err(invalid("invalid next items"));
+        }
+
+        Ok(chain)
+    }
+
+    /// These bytes, for `from_bytes`.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// A copy of these bytes, for `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.to_vec()
+    }
+
+    /// The max look a head size.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Generates a new term with a custom lookbehind, using `rng`. Empty if the chain was
+    /// untrained.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_with_rng<R: Rng + ?Sized>(&self, rng: &mut R, max_lookbehind: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut term = Vec::new();
+        let mut curr: Vec<u32> = vec![0];
+
+        loop {
+            // Fall back to shorter stages, and then the alphabet, like `choose_next_by`
+            let next = loop {
+                if let Some(idx) = self.find_stage(&curr) {
+                    let (first, len, total) = self.stage_next(idx);
+                    break weighted_choice(rng, total, (first..first + len).map(|i| self.next(i)));
+                }
+                if curr.len() == 1 {
+                    let items = (0..self.num_items).map(|i| (i as u32 + 1, self.item(i).1));
+                    break weighted_choice(rng, self.total, items);
+                }
+                curr.remove(0);
+            };
+
+            match next {
+                None | Some(0) => return term,
+                Some(id) => {
+                    let item = self.item(id as usize - 1).0;
+                    term.push(T::from_u32(item).expect("checked in from_bytes"));
+                    curr.push(id);
+                    if curr.len() > max_lookbehind {
+                        curr.remove(0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like `generate_with_rng`, but returns an error if `max_lookbehind` is invalid, or the
+    /// chain was untrained, like `MarkovChain::try_generate_max_look`.
+    pub fn try_generate_with_rng<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        max_lookbehind: usize,
+    ) -> Result<Vec<T>> {
+        if max_lookbehind == 0 || max_lookbehind > self.size {
+            return Err(Error::InvalidLookbehind {
+                max_lookbehind,
+                size: self.size,
+            });
+        }
+        if self.total == 0 && self.find_stage(&[0]).is_none() {
+            return Err(Error::Untrained);
+        }
+        Ok(self.generate_with_rng(rng, max_lookbehind))
+    }
+
+    fn word(&self, idx: usize) -> u32 {
+        read(&self.bytes, idx)
+    }
+
+    fn item(&self, idx: usize) -> (u32, u32) {
+        let offset = HEADER_LEN + 2 * idx;
+        (self.word(offset), self.word(offset + 1))
+    }
+
+    fn stage_offset(&self, idx: usize) -> usize {
+        HEADER_LEN + 2 * self.num_items + (self.size + 3) * idx
+    }
+
+    fn stage_key(&self, idx: usize) -> impl Iterator<Item = u32> + Clone + '_ {
+        let offset = self.stage_offset(idx);
+        (offset..offset + self.size).map(move |i| self.word(i))
+    }
+
+    /// The index of the first next item, the number of next items, and the total count.
+    fn stage_next(&self, idx: usize) -> (usize, usize, u32) {
+        let offset = self.stage_offset(idx) + self.size;
+        (
+            self.word(offset) as usize,
+            self.word(offset + 1) as usize,
+            self.word(offset + 2),
+        )
+    }
+
+    fn next(&self, idx: usize) -> (u32, u32) {
+        let offset = self.stage_offset(self.num_stages) + 2 * idx;
+        (self.word(offset), self.word(offset + 1))
+    }
+
+    fn find_stage(&self, stage: &[u32]) -> Option<usize> {
+        let key = || {
+            stage
+                .iter()
+                .copied()
+                .chain(std::iter::repeat(PADDING))
+                .take(self.size)
+        };
+        let (mut low, mut high) = (0, self.num_stages);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.stage_key(mid).cmp(key()) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+}
+
+fn invalid(msg: &'static str) -> Error {
+    Error::Malformed(msg)
+}
+
+/// An error if `bytes` isn't a frozen chain in a format version which can be read.
+fn check_version(bytes: &[u8]) -> Result<()> {
+    if bytes.len() < 8 {
+        return Err(invalid("too short"));
+    }
+    let supported = FrozenChain::<u32>::FORMAT_VERSION;
+    match (read(bytes, 0).to_le_bytes(), read(bytes, 1)) {
+        (MAGIC, 0) => Err(invalid("the format version is 0")),
+        (MAGIC, version) if version <= supported => Ok(()),
+        (MAGIC, version) => Err(Error::UnsupportedVersion { version, supported }),
+        _ => Err(invalid("not a frozen chain")),
+    }
+}
+
+fn read(bytes: &[u8], idx: usize) -> u32 {
+    let bytes = &bytes[4 * idx..4 * idx + 4];
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Like `super::weighted_choice`, over `(key, count)` pairs in order. `None` if `total` is 0.
+fn weighted_choice<R: Rng + ?Sized>(
+    rng: &mut R,
+    total: u32,
+    options: impl Iterator<Item = (u32, u32)>,
+) -> Option<u32> {
+    if total == 0 {
+        return None;
+    }
+    let random_number = rng.gen_range(0, total as usize);
+    let mut curr_value = 0;
+    let mut last_key = None;
+    for (key, count) in options {
+        last_key = Some(key);
+        if random_number >= curr_value && random_number < curr_value + count as usize {
+            return Some(key);
+        }
+        curr_value += count as usize;
+    }
+
+    last_key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seeded_rng;
+
+    fn chain() -> MarkovChain<char, ()> {
+        let mut mc = MarkovChain::without_rng(3);
+        for word in ["abc", "bbc", "acb", "rust", "trust", "crust", "bust", ""] {
+            mc.train(word.chars());
+        }
+        mc
+    }
+
+    #[test]
+    fn frozen_matches_chain() {
+        let mc = chain();
+        let frozen = mc.freeze();
+        assert_eq!(frozen.size(), 3);
+        for max_lookbehind in 1..=3 {
+            for seed in 0..50 {
+                assert_eq!(
+                    frozen.generate_with_rng(&mut seeded_rng(seed), max_lookbehind),
+                    mc.generate_with_rng(&mut seeded_rng(seed), max_lookbehind)
+                );
+            }
+        }
+
+        let bytes = frozen.to_bytes();
+        let read = FrozenChain::<char>::from_bytes(&bytes).unwrap();
+        assert_eq!(read.as_bytes(), frozen.as_bytes());
+        assert_eq!(read.generate_with_rng(&mut seeded_rng(7), 3), mc.nth(7, 3));
+
+        let empty = MarkovChain::<u8, ()>::without_rng(2).freeze();
+        let read = FrozenChain::<u8>::from_bytes(empty.as_bytes()).unwrap();
+        assert_eq!(
+            read.generate_with_rng(&mut seeded_rng(1), 2),
+            Vec::<u8>::new()
+        );
+        assert_eq!(
+            read.try_generate_with_rng(&mut seeded_rng(1), 2),
+            Err(Error::Untrained)
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn try_freeze_too_big() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train_counted([(vec!['a'], u32::MAX as usize + 1)]);
+        assert_eq!(
+            mc.try_freeze().unwrap_err(),
+            Error::InvalidArgument("the chain is too big to freeze")
+        );
+        assert!(chain().try_freeze().is_ok());
+    }
+
+    #[test]
+    fn from_bytes_versions() {
+        let mc = chain();
+        let bytes = mc.freeze().to_bytes();
+        assert_eq!(read(&bytes, 1), FrozenChain::<char>::FORMAT_VERSION);
+
+        let mut zero = bytes.clone();
+        zero[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(
+            FrozenChain::<char>::from_bytes(&zero).unwrap_err(),
+            Error::Malformed("the format version is 0")
+        );
+
+        let mut newer = bytes.clone();
+        newer[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            FrozenChain::<char>::from_bytes(&newer).unwrap_err(),
+            Error::UnsupportedVersion {
+                version: 2,
+                supported: 1
+            }
+        );
+    }
+
+    #[test]
+    fn from_bytes_invalid() {
+        let bytes = chain().freeze().to_bytes();
+        assert_eq!(
+            FrozenChain::<char>::from_bytes(&bytes[..bytes.len() - 4]).unwrap_err(),
+            Error::Malformed("wrong length")
+        );
+        assert_eq!(
+            FrozenChain::<char>::from_bytes(&bytes[4..]).unwrap_err(),
+            Error::Malformed("not a frozen chain")
+        );
+        assert_eq!(
+            FrozenChain::<char>::from_bytes(&[]).unwrap_err(),
+            Error::Malformed("too short")
+        );
+        assert!(FrozenChain::<u8>::from_bytes(&bytes).is_ok());
+
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train("λ".chars());
+        assert_eq!(
+            FrozenChain::<u8>::from_bytes(mc.freeze().as_bytes()).unwrap_err(),
+            Error::Malformed("invalid item")
+        );
+
+        // Every single changed word is noticed or harmless
+        for idx in 0..bytes.len() / 4 {
+            let mut bytes = bytes.clone();
+            bytes[4 * idx..4 * idx + 4].copy_from_slice(&0xfffffu32.to_le_bytes());
+            if let Ok(frozen) = FrozenChain::<char>::from_bytes(&bytes) {
+                frozen.generate_with_rng(&mut seeded_rng(idx as u64), 3);
+            }
+        }
+    }
+}
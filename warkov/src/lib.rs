@@ -7,6 +7,49 @@
 //! When generating a new term, it looks at the previous X items that it has already output to
 //! decide the next item. This number controls this.
 //!
+//! ## Errors
+//! Methods which can panic, e.g. when given a lookbehind of 0, have a `try_` version which
+//! returns an [`Error`] instead, such as [`MarkovChain::try_generate`].
+//!
+//! Generating from a chain which hasn't been trained doesn't panic, but gives empty terms. The
+//! `try_` versions of the ways to generate return [`Error::Untrained`] instead.
+//!
+//! ## Saving chains
+//! [`MarkovChain::write_csv`] saves a chain as CSV, which [`MarkovChain::read_csv`] can load.
+//!
+//! [`MarkovChain::freeze`] makes a read only [`FrozenChain`], which is stored as one flat block
+//! of bytes. It can be saved with `to_bytes`, and used straight from those bytes, e.g. from a
+//! memory mapped file, with `from_bytes`. The bytes have a format version, so bytes saved by a
+//! newer version of warkov give an [`Error::UnsupportedVersion`].
+//!
+//! With the `serde` feature, a chain can be saved in any serde format, e.g. JSON. The random
+//! number generator isn't saved, so a chain is loaded as a `MarkovChain<T, ()>`, and given a
+//! generator with [`MarkovChain::with_rng`]. Saved chains have a format version too, so chains
+//! from a newer version of warkov give an error wrapping [`Error::UnsupportedVersion`].
+//!
+//! ## Optional features
+//! * `rayon`: [`MarkovChain::generate_many_parallel`] to generate many terms across threads.
+//! * `unicode-normalization`: `FoldedChar`, for chains which ignore case and diacritics.
+//! * `serde`: `Serialize` for [`ChainStats`], and `Serialize` and `Deserialize` for
+//!   [`MarkovChain`], see below.
+//! * `gzip` and `zstd`: reading compressed word lists with [`Compression`], e.g. in
+//!   [`MarkovChain::train_from_file`].
+//! * `deterministic`: keeps the stages in a sorted map, rather than a `HashMap`, so results which
+//!   add up floating point numbers over the stages, like [`MarkovChain::kl_divergence`], are the
+//!   same every run, at the cost of slower training.
+//!
+//! ## Items
+//! A chain can be made of any item which is `Hash + Eq + Clone + Default + Ord + Debug`, not just
+//! `char`. To model several things at each position, e.g. the pitch and duration of notes, use a
+//! tuple, or a struct deriving those traits. The start and end of terms are stored separately, so
+//! no value of the item (not even its `Default`) is treated as special.
+//!
+//! ## Random number generators
+//! Generating needs a random number generator, which is stored in the chain. Chains which are
+//! only trained and inspected can be made with [`MarkovChain::without_rng`], and given one later
+//! with [`MarkovChain::with_rng`]. [`MarkovChain::from_seed`] makes a chain with a seeded
+//! generator, which always generates the same terms after the same training.
+//!
 //! # Example
 //!
 //! ```
@@ -22,26 +65,245 @@
 //!
 //! // Generate a new word
 //! let new_word: Vec<char> = mc.generate();
+//!
+//! // Or, for chains of `char`, get it as a `String` with a lookbehind of 3
+//! let new_word: String = mc.generate_string(3);
 //! ```
 extern crate rand;
 
 pub use rand::Rng;
-use std::collections::{BTreeMap, HashMap};
+use std::borrow::Cow;
+#[cfg(not(feature = "deterministic"))]
+use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::ops::ControlFlow;
+
+mod csv;
+mod distribution;
+mod error;
+mod files;
+#[cfg(feature = "unicode-normalization")]
+mod folded;
+mod frozen;
+mod mixture;
+#[cfg(feature = "serde")]
+mod serialize;
+mod session;
+mod stats;
+mod tagged;
+
+pub use distribution::TermDistribution;
+pub use error::{Error, Result};
+pub use files::Compression;
+#[cfg(feature = "unicode-normalization")]
+pub use folded::FoldedChar;
+pub use frozen::{FrozenChain, FrozenItem};
+pub use mixture::{MixtureGenerator, Strategy};
+pub use session::GenerationSession;
+pub use stats::ChainStats;
+pub use tagged::TaggedChain;
 
 /// A Markov Chain.
 ///
-#[derive(Default)]
+/// `R` is the random number generator used to generate new terms. Training and inspecting the
+/// chain doesn't need one, so chains made with `without_rng` use `()`.
+///
+/// A chain can be cloned if its random number generator can, e.g. to make several variants of
+/// one trained chain. The clone has a copy of the generator, so they generate the same terms
+/// until one is changed. It also has a copy of the running totals which `generate_max_look`
+/// keeps, one list for each stage chosen from, as long as the next items of the stage, and of
+/// the openings kept for `StartMode::Openings`.
+#[derive(Clone, Default)]
 pub struct MarkovChain<T, R>
 where
     T: Hash + Eq + Clone + Default + Ord + Debug,
-    R: Rng,
 {
     size: usize,
     rng: R,
-    stages: HashMap<Vec<Option<T>>, (usize, BTreeMap<Option<T>, usize>)>,
-    alphabet: (usize, BTreeMap<T, usize>),
+    stages: Stages<T>,
+    alphabet: Alphabet<T>,
+    /// How many terms with both boundaries there were of each length in training.
+    lengths: BTreeMap<usize, usize>,
+    /// For each class from `train_with_aliases`, how often each item was seen as it.
+    members: BTreeMap<T, BTreeMap<T, usize>>,
+    start_mode: StartMode,
+    rounding: RoundingMode,
+    /// Built as generating needs it, and cleared when the stages change.
+    cumulative: Cumulative<T>,
+    /// The openings of each lookbehind, built as generating needs them, and cleared when the
+    /// stages change.
+    openings: BTreeMap<usize, Openings<T>>,
+}
+
+/// The total count, and how often each next item was seen.
+type Counts<T> = (usize, BTreeMap<Boundary<T>, usize>);
+
+/// Something for each stage. With the `deterministic` feature, this is sorted, so going through
+/// it always happens in the same order.
+#[cfg(not(feature = "deterministic"))]
+type StageMap<T, V> = HashMap<Vec<Boundary<T>>, V>;
+#[cfg(feature = "deterministic")]
+type StageMap<T, V> = BTreeMap<Vec<Boundary<T>>, V>;
+
+/// The counts for each stage (the previous items).
+type Stages<T> = StageMap<T, Counts<T>>;
+
+/// For each stage which has been sampled from since it last changed, its next items, each with
+/// the total of the counts up to and including it, so choosing one is a binary search.
+type Cumulative<T> = StageMap<T, Vec<(usize, Boundary<T>)>>;
+
+/// The stages of some lookbehind at the start of a training term, with how often each was seen,
+/// and their total.
+type Openings<T> = (usize, BTreeMap<Vec<Boundary<T>>, usize>);
+
+/// The total count, and how often each item was seen, regardless of position.
+type Alphabet<T> = (usize, BTreeMap<T, usize>);
+
+/// One item of a term, with the start and end of the term made explicit.
+///
+/// This is how terms are stored internally, and can be passed to
+/// [`MarkovChain::train_with_boundaries`] to control where terms start and end.
+///
+/// The variant order matters: `End` sorts before every `Tok`, so iterating over the possible next
+/// items visits the end of the term first.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Boundary<T> {
+    /// Before the first item of a term. Only found at the front of a stage.
+    Start,
+    /// After the last item of a term. Only found as a next item, never in a stage.
+    #[default]
+    End,
+    /// An actual item of the term.
+    Tok(T),
+}
+
+/// What comes next after part of a term: another item, or the end of the term.
+///
+/// Like `Boundary`, `End` sorts before every `Tok`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NextToken<T> {
+    /// The term ends here.
+    End,
+    /// The term continues with this item.
+    Tok(T),
+}
+
+impl<T> NextToken<T> {
+    /// `None` for `Boundary::Start`, which never comes next.
+    fn from_boundary(boundary: Boundary<T>) -> Option<Self> {
+        match boundary {
+            Boundary::Start => None,
+            Boundary::End => Some(NextToken::End),
+            Boundary::Tok(t) => Some(NextToken::Tok(t)),
+        }
+    }
+}
+
+/// How to choose between equally likely items, when always choosing the most likely item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// The smallest item, with the end of the term before everything else.
+    #[default]
+    First,
+    /// The largest item.
+    Last,
+    /// A random one, using the random number generator of the chain.
+    Random,
+}
+
+/// How `generate`, `generate_max_look`, `generate_iter`, `generate_collect` and `generate_string`
+/// choose the first items of a term, set with `set_start_mode`. Other ways of generating aren't
+/// changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StartMode {
+    /// Choose each item after the ones before it, like every other item of the term.
+    #[default]
+    Incremental,
+    /// Choose the first `max_lookbehind - 1` items together from the openings of the training
+    /// terms which were that long, like `generate_authentic_start`, so terms start like one of
+    /// them, and then carry on as usual. If there weren't any terms that long, this is the same
+    /// as `Incremental`. The openings are found once for each lookbehind, by looking through every
+    /// stage, and kept until the stages change.
+    Openings,
+}
+
+/// What `generate_budget` does with the last term, when it has more items than are left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BudgetOverflow {
+    /// Keep the start of it, so the items add up to exactly the budget.
+    #[default]
+    Truncate,
+    /// Leave it out, so every term is whole, but the items might not add up to the budget.
+    Discard,
+}
+
+/// How to turn scaled counts back into whole numbers, in `merge_weighted`, `update` and
+/// `quantize`, which use the mode set with `set_rounding_mode`, and in
+/// `merge_weighted_with_rounding` and `update_with_rounding`, which are given one. Counts which
+/// become 0 are removed, and the totals are always worked out again from the rounded counts, so
+/// they stay consistent with them, but they can be more or less than the scaled totals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoundingMode {
+    /// Round down. Every count which isn't already whole gets smaller, so rare items are removed
+    /// quickly, and the totals shrink.
+    Floor,
+    /// Round to the nearest whole number. Counts less than a half are removed.
+    Round,
+    /// Round up, so no count which was more than 0 is ever removed, but the counts of rare items
+    /// are made bigger, and never go below 1, so the totals grow. This is the default, so rare
+    /// items aren't lost.
+    #[default]
+    Ceil,
+    /// Round up with a probability of the part after the decimal point, otherwise down, using
+    /// the random number generator of the chain, so a seeded chain always rounds the same way.
+    /// On average, counts and totals keep their value, so rare items are sometimes removed, and
+    /// sometimes kept.
+    StochasticRound,
+}
+
+impl RoundingMode {
+    fn round<R: Rng + ?Sized>(self, x: f64, rng: &mut R) -> usize {
+        match self {
+            RoundingMode::Floor => x.floor() as usize,
+            RoundingMode::Round => x.round() as usize,
+            RoundingMode::Ceil => x.ceil() as usize,
+            RoundingMode::StochasticRound => {
+                let floor = x.floor();
+                let up = rng.gen::<f64>() < x - floor;
+                floor as usize + usize::from(up)
+            }
+        }
+    }
+}
+
+/// Which items are allowed at one position of a term, for `generate_matching`.
+#[derive(Debug, Clone)]
+pub enum TokenClass<T> {
+    /// Only this item.
+    Exact(T),
+    /// Any of these items.
+    OneOf(BTreeSet<T>),
+    /// Any item for which this returns true, e.g. `|c| c.is_ascii_digit()`.
+    Matching(fn(&T) -> bool),
+    /// Any item.
+    Any,
+}
+
+impl<T: Ord> TokenClass<T> {
+    /// Whether `t` is allowed by this.
+    pub fn matches(&self, t: &T) -> bool {
+        match self {
+            TokenClass::Exact(item) => item == t,
+            TokenClass::OneOf(items) => items.contains(t),
+            TokenClass::Matching(f) => f(t),
+            TokenClass::Any => true,
+        }
+    }
 }
 
 impl<T> MarkovChain<T, rand::ThreadRng>
@@ -56,6 +318,70 @@ where
     pub fn new(size: usize) -> Self {
         MarkovChain::new_with_rng(size, rand::thread_rng())
     }
+
+    /// Like `new`, but returns an error if `size` is 0.
+    pub fn try_new(size: usize) -> Result<Self> {
+        MarkovChain::try_new_with_rng(size, rand::thread_rng())
+    }
+}
+
+impl<T> MarkovChain<T, rand::prng::ChaChaRng>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    /// Creates a MarkovChain with the max look a head size, and a random number generator made
+    /// from `seed`, so chains with the same seed, trained on the same terms, generate the same
+    /// terms, e.g. for tests, or to share a seed which gives good terms. The generator is the
+    /// same as `nth` uses.
+    ///
+    /// # Panics
+    /// If size is 0.
+    pub fn from_seed(size: usize, seed: u64) -> Self {
+        MarkovChain::new_with_rng(size, seeded_rng(seed))
+    }
+
+    /// Like `from_seed`, but returns an error if `size` is 0.
+    pub fn try_from_seed(size: usize, seed: u64) -> Result<Self> {
+        MarkovChain::try_new_with_rng(size, seeded_rng(seed))
+    }
+
+    /// Starts the random number generator again from `seed`, so this generates the same terms
+    /// as a chain from `from_seed` with `seed` which was trained on the same terms.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = seeded_rng(seed);
+    }
+}
+
+impl<T> MarkovChain<T, ()>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    /// Creates a MarkovChain with the max look a head size, which can be trained and inspected,
+    /// but not used to generate until it's given a random number generator with `with_rng`.
+    ///
+    /// # Panics
+    /// If size is 0.
+    pub fn without_rng(size: usize) -> Self {
+        assert!(size > 0);
+        MarkovChain {
+            size,
+            rng: (),
+            stages: StageMap::new(),
+            alphabet: (0, BTreeMap::new()),
+            lengths: BTreeMap::new(),
+            members: BTreeMap::new(),
+            start_mode: StartMode::default(),
+            rounding: RoundingMode::default(),
+            cumulative: StageMap::new(),
+            openings: BTreeMap::new(),
+        }
+    }
+
+    /// Like `without_rng`, but returns an error if `size` is 0.
+    pub fn try_without_rng(size: usize) -> Result<Self> {
+        check_size(size)?;
+        Ok(MarkovChain::without_rng(size))
+    }
 }
 
 impl<T, R> MarkovChain<T, R>
@@ -72,44 +398,36 @@ where
         MarkovChain {
             size,
             rng,
-            stages: HashMap::new(),
+            stages: StageMap::new(),
             alphabet: (0, BTreeMap::new()),
+            lengths: BTreeMap::new(),
+            members: BTreeMap::new(),
+            start_mode: StartMode::default(),
+            rounding: RoundingMode::default(),
+            cumulative: StageMap::new(),
+            openings: BTreeMap::new(),
         }
     }
 
+    /// Like `new_with_rng`, but returns an error if `size` is 0.
+    pub fn try_new_with_rng(size: usize, rng: R) -> Result<Self> {
+        check_size(size)?;
+        Ok(MarkovChain::new_with_rng(size, rng))
+    }
+
     /// Change the random number generation for this object to `rng`.
     pub fn set_rng(&mut self, rng: R) {
         self.rng = rng
     }
 
-    fn record_occurance(&mut self, mut stage: &[Option<T>], next: Option<T>) {
-        while !stage.is_empty() {
-            let stage_stat = self.stages.entry(stage.to_vec()).or_default();
-            stage_stat.0 += 1;
-            *stage_stat.1.entry(next.clone()).or_default() += 1;
-
-            stage = &stage[1..];
-        }
+    /// Change how generating chooses the first items of a term, see `StartMode`.
+    pub fn set_start_mode(&mut self, start_mode: StartMode) {
+        self.start_mode = start_mode
     }
 
-    /// Teach the markov chain this `term`.
-    pub fn train(&mut self, term: impl Iterator<Item = T>) {
-        let term: Vec<T> = term.into_iter().collect();
-        self.alphabet.0 += term.len();
-        for t in term.iter() {
-            *self.alphabet.1.entry(t.clone()).or_default() += 1;
-        }
-        let mut term: Vec<Option<T>> = term.into_iter().map(|s| Some(s)).collect();
-        term.insert(0, None);
-        term.push(None);
-
-        for idx in 1..term.len() {
-            for len in 1..(self.size + 1) {
-                if len <= idx {
-                    self.record_occurance(&term[idx - len..idx], term[idx].clone());
-                }
-            }
-        }
+    /// Change how `merge_weighted`, `update` and `quantize` round counts, see `RoundingMode`.
+    pub fn set_rounding_mode(&mut self, rounding: RoundingMode) {
+        self.rounding = rounding
     }
 
     /// Generates a term.
@@ -119,216 +437,6198 @@ where
         self.generate_max_look(curr_size)
     }
 
+    /// Like `generate`, but returns an error if this chain hasn't been trained.
+    pub fn try_generate(&mut self) -> Result<Vec<T>> {
+        self.try_generate_max_look(self.size)
+    }
+
     /// Generates a new term with a custom lookbehind.
+    ///
+    /// Running totals of the counts of each stage chosen from are kept, so choosing from it
+    /// again is a binary search, until training changes it. They take as much memory again as
+    /// the next items of every stage chosen from, which for a long run is most of the chain.
+    /// `generate_iter`, `token_stream` and `generate_authentic_start` keep them too, but the
+    /// other ways to generate, and `weighted_choice`, go through the next items each time.
     /// # Panics
     /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
     pub fn generate_max_look(&mut self, max_lookbehind: usize) -> Vec<T> {
-        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        self.generate_iter(max_lookbehind).collect()
+    }
 
-        let mut result = Vec::new();
-        let mut curr: Vec<Option<T>> = vec![None];
-        let mut next: Option<T>;
+    /// Like `generate_max_look`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_max_look(&mut self, max_lookbehind: usize) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_max_look(max_lookbehind))
+    }
 
-        loop {
-            loop {
-                match self.stages.get(&curr) {
-                    None => {
-                        if curr.len() == 1 {
-                            next = Some(weighted_choice(&mut self.rng, &self.alphabet));
-                            break;
-                        } else {
-                            curr.remove(0);
-                            continue;
-                        }
-                    }
-                    Some(stats) => {
-                        next = weighted_choice(&mut self.rng, stats);
-                        break;
-                    }
-                }
-            }
+    /// Generates a new term with a custom lookbehind, collected into any collection, e.g. a
+    /// `String` for chains of `char`.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_collect<C: FromIterator<T>>(&mut self, max_lookbehind: usize) -> C {
+        self.generate_iter(max_lookbehind).collect()
+    }
 
-            if next == None {
-                // we're at end
-                break;
-            } else {
-                curr.push(next.clone());
-                while curr.len() > max_lookbehind {
-                    curr.remove(0);
-                }
-                result.push(next.clone().unwrap());
-            }
+    /// Like `generate_collect`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_collect<C: FromIterator<T>>(&mut self, max_lookbehind: usize) -> Result<C> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_collect(max_lookbehind))
+    }
+
+    /// Generates `samples` terms with a custom lookbehind, and returns how many terms there were
+    /// of each length.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn length_histogram(
+        &mut self,
+        samples: usize,
+        max_lookbehind: usize,
+    ) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for _ in 0..samples {
+            *histogram
+                .entry(self.generate_max_look(max_lookbehind).len())
+                .or_default() += 1;
         }
+        histogram
+    }
 
-        result
+    /// Like `length_histogram`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_length_histogram(
+        &mut self,
+        samples: usize,
+        max_lookbehind: usize,
+    ) -> Result<BTreeMap<usize, usize>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.length_histogram(samples, max_lookbehind))
     }
-}
 
-fn weighted_choice<T: Debug + Clone + Default, R: Rng>(
-    rng: &mut R,
-    options: &(usize, BTreeMap<T, usize>),
-) -> T {
-    debug_assert_eq!(options.0, options.1.values().sum());
-    let random_number = rng.gen_range(0, options.0);
-    let mut curr_value = 0;
-    let mut last_key = &T::default();
-    for (key, value) in options.1.iter() {
-        last_key = key;
-        if random_number >= curr_value && random_number < curr_value + value {
-            return key.clone();
+    /// Generates a new term with a custom lookbehind, which is cut off at the `percentile`th
+    /// percentile of the lengths of the terms this was trained on, e.g. 95 means no longer than
+    /// 95% of training terms. Terms which would have ended sooner aren't changed.
+    ///
+    /// Only terms trained with both boundaries, e.g. with `train`, have a length. If there are
+    /// none, like in a chain from `read_csv`, terms aren't cut off.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if
+    /// `percentile` isn't between 0 and 100.
+    pub fn generate_typical_length(&mut self, max_lookbehind: usize, percentile: f64) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let max_len = self.length_percentile(percentile).unwrap_or(usize::MAX);
+        self.token_stream(max_lookbehind).take(max_len).collect()
+    }
+
+    /// Like `generate_typical_length`, but returns an error if `max_lookbehind` or `percentile` is
+    /// invalid, or this chain hasn't been trained.
+    pub fn try_generate_typical_length(
+        &mut self,
+        max_lookbehind: usize,
+        percentile: f64,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        if !(0. ..=100.).contains(&percentile) {
+            return Err(Error::InvalidArgument(
+                "percentile must be between 0 and 100",
+            ));
         }
-        curr_value += value;
+        Ok(self.generate_typical_length(max_lookbehind, percentile))
     }
 
-    last_key.to_owned()
-}
+    /// Generates a new term of exactly `len` items, with a custom lookbehind.
+    ///
+    /// The end of the term is never chosen before there are `len` items, and the term only ends
+    /// after `len` items if this chain could end it there. If instead it gets to a stage where only
+    /// the end could come next, or can't end after `len` items, it starts again. This means the
+    /// result could always have been generated by `generate_max_look`.
+    ///
+    /// Returns `None` if no term was found after `max_attempts` attempts, which will always
+    /// happen if no term of that length is possible.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_exact_len(
+        &mut self,
+        max_lookbehind: usize,
+        len: usize,
+        max_attempts: usize,
+    ) -> Option<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        for _ in 0..max_attempts {
+            let rng = &mut self.rng;
+            let mut position = 0;
+            let term: Vec<T> = tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+                let end_count = stats.1.get(&Boundary::End).copied().unwrap_or(0);
+                let choice = if position < len && end_count > 0 && end_count < stats.0 {
+                    let mut without_end = stats.clone();
+                    without_end.1.remove(&Boundary::End);
+                    without_end.0 -= end_count;
+                    weighted_choice_counts(rng, &without_end)
+                } else if position == len && end_count > 0 {
+                    Boundary::End
+                } else {
+                    weighted_choice_counts(rng, stats)
+                };
+                position += 1;
+                Some(choice)
+            })
+            .take(len + 1)
+            .collect();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rand::SeedableRng;
+            if term.len() == len {
+                return Some(term);
+            }
+        }
 
-    fn has_key<R: Rng>(mc: &MarkovChain<char, R>, k: &str) -> bool {
-        let k: Vec<Option<char>> = k.chars().map(|s| Some(s.clone())).collect();
-        mc.stages.contains_key(&k)
+        None
     }
 
-    fn has_key_w_none_prefix<R: Rng>(mc: &MarkovChain<char, R>, k: &str) -> bool {
-        let mut k: Vec<Option<char>> = k.chars().map(|s| Some(s.clone())).collect();
-        k.insert(0, None);
-        mc.stages.contains_key(&k)
+    /// Like `generate_exact_len`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_exact_len(
+        &mut self,
+        max_lookbehind: usize,
+        len: usize,
+        max_attempts: usize,
+    ) -> Result<Option<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_exact_len(max_lookbehind, len, max_attempts))
     }
 
-    fn has_key_w_none_predict<R: Rng>(mc: &MarkovChain<char, R>, k: &str) -> bool {
-        let k: Vec<Option<char>> = k.chars().map(|s| Some(s.clone())).collect();
-        mc.stages
-            .get(&k)
-            .map(|stats| stats.1.contains_key(&None))
-            .unwrap_or(false)
+    /// Generates a new term with a custom lookbehind, of at least `min_len` and at most `max_len`
+    /// items, e.g. for names which mustn't be too short or too long.
+    ///
+    /// A term which gets to `max_len` items is cut off there, wherever the chain would have
+    /// ended it. A term shorter than `min_len` is thrown away, and `None` is returned if no term
+    /// was long enough after `max_attempts` attempts, which will always happen if the chain can't
+    /// make terms that long.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if
+    /// `min_len` is more than `max_len`.
+    pub fn generate_with_length_bounds(
+        &mut self,
+        max_lookbehind: usize,
+        min_len: usize,
+        max_len: usize,
+        max_attempts: usize,
+    ) -> Option<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        assert!(min_len <= max_len, "min_len must not be more than max_len");
+        for _ in 0..max_attempts {
+            let term: Vec<T> = self.token_stream(max_lookbehind).take(max_len).collect();
+            if term.len() >= min_len {
+                return Some(term);
+            }
+        }
+
+        None
     }
 
-    #[test]
-    fn simple1() {
-        let mut mc = MarkovChain::new(2);
+    /// Like `generate_with_length_bounds`, but returns an error if `max_lookbehind` is invalid, or
+    /// `min_len` is more than `max_len`, or this chain hasn't been trained.
+    pub fn try_generate_with_length_bounds(
+        &mut self,
+        max_lookbehind: usize,
+        min_len: usize,
+        max_len: usize,
+        max_attempts: usize,
+    ) -> Result<Option<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        if min_len > max_len {
+            return Err(Error::InvalidArgument(
+                "min_len must not be more than max_len",
+            ));
+        }
+        Ok(self.generate_with_length_bounds(max_lookbehind, min_len, max_len, max_attempts))
+    }
 
-        mc.train("abc".chars());
-        //assert_eq!(mc.stages.len(), 4, "{:?}", mc.stages);
-        assert!(mc.stages.contains_key(&vec![None]));
-        assert!(has_key_w_none_prefix(&mc, "a"));
-        assert!(!has_key_w_none_prefix(&mc, "ab"));
-        assert!(!has_key_w_none_prefix(&mc, "abc"));
+    /// Generates a new term with a custom lookbehind, always choosing the most likely next item.
+    ///
+    /// This can loop forever, so it stops after `max_len` items.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_greedy(
+        &mut self,
+        max_lookbehind: usize,
+        max_len: usize,
+        tie_break: TieBreak,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let rng = &mut self.rng;
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            most_likely(&stats.1, tie_break, rng)
+        })
+        .take(max_len)
+        .collect()
+    }
 
-        assert!(has_key_w_none_predict(&mc, "c"));
-        assert!(has_key_w_none_predict(&mc, "bc"));
-        assert!(!has_key_w_none_predict(&mc, "abc"));
+    /// Like `generate_greedy`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_greedy(
+        &mut self,
+        max_lookbehind: usize,
+        max_len: usize,
+        tie_break: TieBreak,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_greedy(max_lookbehind, max_len, tie_break))
+    }
 
-        assert!(has_key(&mc, "ab"));
-        assert!(has_key(&mc, "ab"));
-        assert!(has_key(&mc, "bc"));
-        assert!(!has_key(&mc, "ac"));
+    /// Generates a new term with a custom lookbehind, calling `on_token` with each item as it's
+    /// chosen.
+    ///
+    /// `on_token` gets the items before it which were used to choose it (at most
+    /// `max_lookbehind`, fewer at the start of the term), and the item itself. Returning
+    /// `ControlFlow::Break` stops the term after that item. Returns the term so far.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_with_callback(
+        &mut self,
+        max_lookbehind: usize,
+        mut on_token: impl FnMut(&[T], &T) -> ControlFlow<()>,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut term = Vec::new();
+        for t in self.token_stream(max_lookbehind) {
+            let flow = on_token(&term[term.len().saturating_sub(max_lookbehind)..], &t);
+            term.push(t);
+            if flow.is_break() {
+                break;
+            }
+        }
+        term
+    }
 
-        assert!(has_key(&mc, "a"));
-        assert!(has_key(&mc, "b"));
-        assert!(has_key(&mc, "c"));
-        assert!(!has_key(&mc, "d"));
+    /// Like `generate_with_callback`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_with_callback(
+        &mut self,
+        max_lookbehind: usize,
+        on_token: impl FnMut(&[T], &T) -> ControlFlow<()>,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_with_callback(max_lookbehind, on_token))
     }
 
-    #[test]
-    fn simple2() {
-        let mut mc = MarkovChain::new(3);
+    /// Generates a new term with a custom lookbehind, choosing each item evenly from the
+    /// `top_rank` most common next items, ignoring how much more common some of them are.
+    ///
+    /// Ties in how common items are go to the smallest item, with the end of the term first. If
+    /// a stage has fewer than `top_rank` next items, it falls back to the shorter stage, and if
+    /// no stage has enough, the term ends there, rather than choosing from the alphabet.
+    ///
+    /// This can loop forever, so terms are cut off at the length of the longest term this was
+    /// trained on. Only terms trained with both boundaries, e.g. with `train`, have a length. If
+    /// there are none, like in a chain from `read_csv`, terms aren't cut off.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if
+    /// `top_rank` is 0.
+    pub fn generate_rank_limited(&mut self, max_lookbehind: usize, top_rank: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        assert!(top_rank > 0);
+        let max_len = self
+            .lengths
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or(usize::MAX);
+        let rng = &mut self.rng;
+        // Nothing is chosen from an empty alphabet, so the term ends instead
+        let no_alphabet = (0, BTreeMap::new());
+        tokens_by(&self.stages, &no_alphabet, max_lookbehind, |stats| {
+            if stats.1.len() < top_rank {
+                return None;
+            }
+            let mut ranked: Vec<(&Boundary<T>, &usize)> = stats.1.iter().collect();
+            ranked.sort_by(|a, b| b.1.cmp(a.1));
+            Some(ranked[rng.gen_range(0, top_rank)].0.clone())
+        })
+        .take(max_len)
+        .collect()
+    }
 
-        mc.train("abc".chars());
-        assert!(mc.stages.contains_key(&vec![None]));
-        assert!(has_key_w_none_prefix(&mc, "a"));
-        assert!(has_key_w_none_prefix(&mc, "ab"));
-        assert!(!has_key_w_none_prefix(&mc, "abc"));
+    /// Like `generate_rank_limited`, but returns an error if an argument is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_rank_limited(
+        &mut self,
+        max_lookbehind: usize,
+        top_rank: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        if top_rank == 0 {
+            return Err(Error::InvalidArgument("top_rank must be more than 0"));
+        }
+        Ok(self.generate_rank_limited(max_lookbehind, top_rank))
+    }
 
-        assert!(has_key(&mc, "abc"));
+    /// Generates a new term with a custom lookbehind, ignoring any next item (or end) which was
+    /// seen fewer than `min_count` times after its stage, without changing the chain.
+    ///
+    /// If nothing after a stage was seen `min_count` times, it falls back to the shorter stage,
+    /// which has been seen at least as often, and then to the alphabet. If nothing is left there
+    /// either, the term ends. Ignoring a rare end can make this loop forever, so it stops after
+    /// `max_len` items.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_confident(
+        &mut self,
+        max_lookbehind: usize,
+        min_count: usize,
+        max_len: usize,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let rng = &mut self.rng;
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            let confident: BTreeMap<Boundary<T>, usize> = stats
+                .1
+                .iter()
+                .filter(|(_, count)| **count >= min_count)
+                .map(|(next, count)| (next.clone(), *count))
+                .collect();
+            match confident.values().sum() {
+                0 => None,
+                total => Some(weighted_choice_counts(rng, &(total, confident))),
+            }
+        })
+        .take(max_len)
+        .collect()
+    }
 
-        assert!(has_key(&mc, "ab"));
-        assert!(has_key(&mc, "ab"));
-        assert!(has_key(&mc, "bc"));
-        assert!(!has_key(&mc, "ac"));
+    /// Like `generate_confident`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_confident(
+        &mut self,
+        max_lookbehind: usize,
+        min_count: usize,
+        max_len: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_confident(max_lookbehind, min_count, max_len))
+    }
 
-        assert!(has_key(&mc, "a"));
-        assert!(has_key(&mc, "b"));
-        assert!(has_key(&mc, "c"));
-        assert!(!has_key(&mc, "d"));
+    /// Generates a new term with a custom lookbehind, with a temperature which changes steadily
+    /// from `start_temp` for the first item to `end_temp` for item `target_len`, and stays at
+    /// `end_temp` after that.
+    ///
+    /// Each count `c` is weighted as `c^(1 / temperature)`, so 1 is the same probabilities as
+    /// `generate_max_look`, higher temperatures make rare items more likely, and lower ones make
+    /// common items more likely. A low temperature can make this loop forever, like
+    /// `generate_greedy`, so it stops after `max_len` items.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if a
+    /// temperature isn't finite and greater than 0.
+    pub fn generate_annealed(
+        &mut self,
+        max_lookbehind: usize,
+        start_temp: f64,
+        end_temp: f64,
+        target_len: usize,
+        max_len: usize,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        assert!(
+            valid_temperature(start_temp) && valid_temperature(end_temp),
+            "temperatures must be finite and greater than 0"
+        );
+        let rng = &mut self.rng;
+        let mut position = 0;
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            let progress = if target_len == 0 {
+                1.
+            } else {
+                (position as f64 / target_len as f64).min(1.)
+            };
+            position += 1;
+            tempered_choice(
+                rng,
+                &stats.1,
+                start_temp + (end_temp - start_temp) * progress,
+            )
+        })
+        .take(max_len)
+        .collect()
+    }
 
-        mc.train("rust".chars());
-        assert!(mc.stages.contains_key(&vec![None]));
-        assert!(has_key_w_none_prefix(&mc, "r"));
-        assert!(has_key_w_none_prefix(&mc, "ru"));
-        assert!(!has_key_w_none_prefix(&mc, "rus"));
-        assert!(!has_key_w_none_prefix(&mc, "rust"));
-        assert!(has_key(&mc, "r"));
-        assert!(has_key(&mc, "u"));
-        assert!(has_key(&mc, "s"));
-        assert!(has_key(&mc, "t"));
-        assert!(has_key(&mc, "ru"));
-        assert!(has_key(&mc, "us"));
-        assert!(has_key(&mc, "st"));
-        assert!(has_key(&mc, "rus"));
-        assert!(has_key(&mc, "ust"));
-        assert!(!has_key(&mc, "rust"));
+    /// Like `generate_annealed`, but returns an error if `max_lookbehind` or a temperature is
+    /// invalid, or this chain hasn't been trained.
+    pub fn try_generate_annealed(
+        &mut self,
+        max_lookbehind: usize,
+        start_temp: f64,
+        end_temp: f64,
+        target_len: usize,
+        max_len: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        if !valid_temperature(start_temp) || !valid_temperature(end_temp) {
+            return Err(Error::InvalidArgument(
+                "temperatures must be finite and greater than 0",
+            ));
+        }
+        Ok(self.generate_annealed(max_lookbehind, start_temp, end_temp, target_len, max_len))
     }
 
-    fn easy_rng() -> impl Rng {
-        rand::prng::XorShiftRng::from_seed([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])
+    /// Generates a new term with a custom lookbehind, with the same `temperature` (see
+    /// `generate_annealed`) for every item. A temperature of 0 always chooses the most likely
+    /// item, the first of them if there's a tie, like `generate_greedy` with `TieBreak::First`.
+    ///
+    /// Like `generate_annealed`, this stops after `max_len` items.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if
+    /// `temperature` isn't finite, or is less than 0.
+    pub fn generate_with_temperature(
+        &mut self,
+        max_lookbehind: usize,
+        temperature: f64,
+        max_len: usize,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        assert!(
+            temperature == 0. || valid_temperature(temperature),
+            "temperature must be finite and not negative"
+        );
+        let rng = &mut self.rng;
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            if temperature == 0. {
+                most_likely(&stats.1, TieBreak::First, rng)
+            } else {
+                tempered_choice(rng, &stats.1, temperature)
+            }
+        })
+        .take(max_len)
+        .collect()
     }
 
-    #[test]
-    fn weighted_choice1() {
+    /// Like `generate_with_temperature`, but returns an error if `max_lookbehind` or `temperature`
+    /// is invalid, or this chain hasn't been trained.
+    pub fn try_generate_with_temperature(
+        &mut self,
+        max_lookbehind: usize,
+        temperature: f64,
+        max_len: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        if temperature != 0. && !valid_temperature(temperature) {
+            return Err(Error::InvalidArgument(
+                "temperature must be finite and not negative",
+            ));
+        }
+        Ok(self.generate_with_temperature(max_lookbehind, temperature, max_len))
+    }
+
+    /// Generates a new term with a custom lookbehind, which has one item for each position in
+    /// `pattern`, each allowed by that position. Returns `None` if nothing was found after
+    /// `max_attempts` tries.
+    ///
+    /// Only the items allowed at the current position can be chosen next, and the end only after
+    /// the last position. If none of them were seen after a stage, it falls back to the shorter
+    /// stage, and then to the alphabet. If nothing allowed is there either, or the end of the
+    /// term was never seen after the last item, the attempt fails, and it starts again.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_matching(
+        &mut self,
+        max_lookbehind: usize,
+        pattern: &[TokenClass<T>],
+        max_attempts: usize,
+    ) -> Option<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        for _ in 0..max_attempts {
+            let rng = &mut self.rng;
+            let mut position = 0;
+            let mut ended = false;
+            let term: Vec<T> = tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+                let allowed: BTreeMap<Boundary<T>, usize> = stats
+                    .1
+                    .iter()
+                    .filter(|(next, _)| match (next, pattern.get(position)) {
+                        (Boundary::End, None) => true,
+                        (Boundary::Tok(t), Some(class)) => class.matches(t),
+                        _ => false,
+                    })
+                    .map(|(next, count)| (next.clone(), *count))
+                    .collect();
+                let next = match allowed.values().sum() {
+                    0 => return None,
+                    total => weighted_choice_counts(rng, &(total, allowed)),
+                };
+                position += 1;
+                ended = next == Boundary::End;
+                Some(next)
+            })
+            .collect();
+            if ended {
+                return Some(term);
+            }
+        }
+        None
+    }
+
+    /// Like `generate_matching`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_matching(
+        &mut self,
+        max_lookbehind: usize,
+        pattern: &[TokenClass<T>],
+        max_attempts: usize,
+    ) -> Result<Option<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_matching(max_lookbehind, pattern, max_attempts))
+    }
+
+    /// Up to `num` different terms which start with `partial`, with a custom lookbehind, in the
+    /// order they were first generated, e.g. to suggest whole words as someone types. Each term
+    /// includes `partial`.
+    ///
+    /// Each term carries on from `partial` as if it had been generated, falling back to shorter
+    /// stages as usual if `partial` wasn't seen in training. Like `distinct_terms`, it stops
+    /// early after `max_attempts` terms in a row were ones it already had.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn complete(
+        &mut self,
+        partial: &[T],
+        max_lookbehind: usize,
+        num: usize,
+        max_attempts: usize,
+    ) -> Vec<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut seen = BTreeSet::new();
+        let mut terms = Vec::new();
+        let mut duplicates = 0;
+        while terms.len() < num && duplicates < max_attempts {
+            let term = self.continue_term(partial, max_lookbehind);
+            if seen.insert(term.clone()) {
+                terms.push(term);
+                duplicates = 0;
+            } else {
+                duplicates += 1;
+            }
+        }
+        terms
+    }
+
+    /// Like `complete`, but returns an error if `max_lookbehind` is invalid, or this chain hasn't
+    /// been trained.
+    pub fn try_complete(
+        &mut self,
+        partial: &[T],
+        max_lookbehind: usize,
+        num: usize,
+        max_attempts: usize,
+    ) -> Result<Vec<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.complete(partial, max_lookbehind, num, max_attempts))
+    }
+
+    /// Generates a new term with a custom lookbehind, which starts with `prefix`, e.g. for names
+    /// which all start the same way. The term includes `prefix`.
+    ///
+    /// It carries on from `prefix` as if it had been generated, so a short `prefix` is at the
+    /// start of a term, and only the last `max_lookbehind` items of a longer one change what
+    /// comes next. If `prefix` wasn't seen in training, it falls back to shorter stages as
+    /// usual.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_from_prefix(&mut self, prefix: &[T], max_lookbehind: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        self.continue_term(prefix, max_lookbehind)
+    }
+
+    /// Like `generate_from_prefix`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_from_prefix(
+        &mut self,
+        prefix: &[T],
+        max_lookbehind: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_from_prefix(prefix, max_lookbehind))
+    }
+
+    /// `partial`, and then the rest of a term generated after it.
+    fn continue_term(&mut self, partial: &[T], max_lookbehind: usize) -> Vec<T> {
+        let curr = self.context_after(partial, max_lookbehind);
+        let rng = &mut self.rng;
+        let rest = tokens_from(
+            &self.stages,
+            &self.alphabet,
+            curr,
+            max_lookbehind,
+            |stats| Some(weighted_choice_counts(rng, stats)),
+        );
+        partial.iter().cloned().chain(rest).collect()
+    }
+
+    /// Generates a new term with a custom lookbehind, like `generate_max_look`, but avoiding
+    /// items which would lead to a dead end, to make fewer very short terms.
+    ///
+    /// Before each choice, it looks at the stage generating would use after each item which
+    /// could come next. An item is a dead end if only the end of the term was seen after that
+    /// stage. Dead ends are left out of the choice, unless every item is a dead end, in which
+    /// case nothing is left out. The end of the term itself is never left out, so terms still end
+    /// as often as usual where the chain has seen them end. Dead ends are only left out the first
+    /// time the term gets to each stage, so a term can't go round a loop forever by avoiding the
+    /// only way out of it. This changes the probabilities, so the terms are less like the
+    /// training data than from `generate_max_look`.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_smooth(&mut self, max_lookbehind: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut term = Vec::new();
+        let mut curr = vec![Boundary::Start];
+        let mut visited = BTreeSet::new();
+        loop {
+            let counts = self.next_counts(&mut curr).into_owned();
+            let first_visit = visited.insert(curr.clone());
+            let is_dead_end = |next: &Boundary<T>| {
+                let mut after = curr.clone();
+                after.push(next.clone());
+                if after.len() > max_lookbehind {
+                    after.remove(0);
+                }
+                let counts = self.next_counts(&mut after);
+                counts.1.keys().all(|next| *next == Boundary::End)
+            };
+            let alive: BTreeMap<Boundary<T>, usize> = counts
+                .1
+                .iter()
+                .filter(|(next, _)| *next == &Boundary::End || !is_dead_end(next))
+                .map(|(next, count)| (next.clone(), *count))
+                .collect();
+            let next = if first_visit && alive.keys().any(|next| *next != Boundary::End) {
+                weighted_choice_counts(&mut self.rng, &(alive.values().sum(), alive))
+            } else if counts.0 > 0 {
+                weighted_choice_counts(&mut self.rng, &counts)
+            } else {
+                // Untrained
+                Boundary::End
+            };
+
+            match next {
+                Boundary::Tok(t) => {
+                    curr.push(Boundary::Tok(t.clone()));
+                    if curr.len() > max_lookbehind {
+                        curr.remove(0);
+                    }
+                    term.push(t);
+                }
+                _ => return term,
+            }
+        }
+    }
+
+    /// Like `generate_smooth`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_smooth(&mut self, max_lookbehind: usize) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_smooth(max_lookbehind))
+    }
+
+    /// Generates a new term with a custom lookbehind, with add-k (or Laplace, for a `k` of 1)
+    /// smoothing, so items which were never seen after a stage can still come after it. Not to
+    /// be confused with `generate_smooth`, which avoids dead ends.
+    ///
+    /// Every item in the alphabet, and the end of the term, is chosen as if it had been seen `k`
+    /// more times after each stage than it was. A `k` of 0 gives the same probabilities as
+    /// `generate_max_look`. Each choice goes through the whole alphabet, so this is slower than
+    /// `generate_max_look` for chains with many different items. With a `k` more than 0, the end
+    /// can come after any stage, so the term always ends, but a low `k` can still make long
+    /// terms. Use `generate_smoothed_with_max_len` to cut them off.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if `k`
+    /// is negative or not finite.
+    pub fn generate_smoothed(&mut self, max_lookbehind: usize, k: f64) -> Vec<T> {
+        self.generate_smoothed_with_max_len(max_lookbehind, k, usize::MAX)
+    }
+
+    /// Like `generate_smoothed`, but returns an error if `max_lookbehind` or `k` is invalid, or
+    /// this chain hasn't been trained.
+    pub fn try_generate_smoothed(&mut self, max_lookbehind: usize, k: f64) -> Result<Vec<T>> {
+        self.try_generate_smoothed_with_max_len(max_lookbehind, k, usize::MAX)
+    }
+
+    /// Like `generate_smoothed`, but stopping after `max_len` items.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if `k`
+    /// is negative or not finite.
+    pub fn generate_smoothed_with_max_len(
+        &mut self,
+        max_lookbehind: usize,
+        k: f64,
+        max_len: usize,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        assert!(
+            k.is_finite() && k >= 0.,
+            "k must be finite and not negative"
+        );
+        let rng = &mut self.rng;
+        let items = &self.alphabet.1;
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            let weights: Vec<(Boundary<T>, f64)> = std::iter::once(Boundary::End)
+                .chain(items.keys().cloned().map(Boundary::Tok))
+                .map(|next| {
+                    let count = stats.1.get(&next).copied().unwrap_or(0);
+                    (next, count as f64 + k)
+                })
+                .collect();
+            choose_by_weight(rng, &weights)
+        })
+        .take(max_len)
+        .collect()
+    }
+
+    /// Like `generate_smoothed_with_max_len`, but returns an error if `max_lookbehind` or `k` is
+    /// invalid, or this chain hasn't been trained.
+    pub fn try_generate_smoothed_with_max_len(
+        &mut self,
+        max_lookbehind: usize,
+        k: f64,
+        max_len: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        if !k.is_finite() || k < 0. {
+            return Err(Error::InvalidArgument("k must be finite and not negative"));
+        }
+        Ok(self.generate_smoothed_with_max_len(max_lookbehind, k, max_len))
+    }
+
+    /// Generates a term of at most `max_len` items with a custom lookbehind, for which
+    /// `constraint` is true, or `None` if it can't find one.
+    ///
+    /// This is a depth first search over what can come next, rather than generating terms until
+    /// one is allowed, so it works for constraints on the whole term, e.g. "has every vowel",
+    /// which rarely happen by chance. The things which can come next are tried in a random order,
+    /// weighted by their counts, so likely terms are found first. When the end of the term is
+    /// chosen, but `constraint` is false, it tries the next thing instead, and when nothing is
+    /// left to try, it removes the last item and tries the next thing there instead, which is one
+    /// backtrack. It gives up after `max_backtracks` backtracks, or once it has tried every term.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_constrained(
+        &mut self,
+        max_lookbehind: usize,
+        constraint: impl Fn(&[T]) -> bool,
+        max_len: usize,
+        max_backtracks: usize,
+    ) -> Option<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut term = Vec::new();
+        // What's left to try after each part of the term, with the next one to try last
+        let mut options = vec![self.shuffled_next(&term, max_lookbehind)];
+        let mut backtracks = 0;
+        while let Some(left) = options.last_mut() {
+            match left.pop() {
+                Some(Boundary::Tok(t)) if term.len() < max_len => {
+                    term.push(t);
+                    options.push(self.shuffled_next(&term, max_lookbehind));
+                }
+                Some(Boundary::End) if constraint(&term) => return Some(term),
+                Some(_) => {}
+                None => {
+                    backtracks += 1;
+                    if backtracks > max_backtracks {
+                        return None;
+                    }
+                    options.pop();
+                    term.pop();
+                }
+            }
+        }
+        None
+    }
+
+    /// Like `generate_constrained`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_constrained(
+        &mut self,
+        max_lookbehind: usize,
+        constraint: impl Fn(&[T]) -> bool,
+        max_len: usize,
+        max_backtracks: usize,
+    ) -> Result<Option<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_constrained(max_lookbehind, constraint, max_len, max_backtracks))
+    }
+
+    /// Everything which can come after `term`, in a random order weighted by the counts, with
+    /// the one which should be tried first last.
+    fn shuffled_next(&mut self, term: &[T], max_lookbehind: usize) -> Vec<Boundary<T>> {
+        let mut curr = self.context_after(term, max_lookbehind);
+        let mut weights: Vec<(Boundary<T>, f64)> = self
+            .next_counts(&mut curr)
+            .1
+            .iter()
+            .map(|(next, count)| (next.clone(), *count as f64))
+            .collect();
+        let mut shuffled = Vec::with_capacity(weights.len());
+        while let Some(next) = choose_by_weight(&mut self.rng, &weights) {
+            weights.retain(|(other, _)| *other != next);
+            shuffled.push(next);
+        }
+        shuffled.reverse();
+        shuffled
+    }
+
+    /// `term` with each class from `train_with_aliases` replaced by one of the items which was
+    /// seen as it, chosen at random by how often each was seen, so a class which was seen as
+    /// '1' three times as often as '2' becomes '1' three times as often. Items which aren't a
+    /// class are left as they are.
+    pub fn unalias(&mut self, term: &[T]) -> Vec<T> {
+        term.iter()
+            .map(|item| {
+                self.members
+                    .get(item)
+                    .and_then(|members| weighted_choice(&mut self.rng, members))
+                    .unwrap_or_else(|| item.clone())
+            })
+            .collect()
+    }
+
+    /// Generates a new term with a custom lookbehind, with each class from `train_with_aliases`
+    /// replaced by an item, see `unalias`.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_unaliased(&mut self, max_lookbehind: usize) -> Vec<T> {
+        let term = self.generate_max_look(max_lookbehind);
+        self.unalias(&term)
+    }
+
+    /// Like `generate_unaliased`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_unaliased(&mut self, max_lookbehind: usize) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_unaliased(max_lookbehind))
+    }
+
+    /// Generates up to `max_count` different terms with a custom lookbehind, in the order they
+    /// were first generated.
+    ///
+    /// It stops once it has `max_count` terms, or after `max_attempts` generated terms in a row
+    /// were ones it already had, which means the chain probably can't make many more. So getting
+    /// fewer than `max_count` terms means the chain seems to be used up.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn distinct_terms(
+        &mut self,
+        max_lookbehind: usize,
+        max_count: usize,
+        max_attempts: usize,
+    ) -> Vec<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut seen = BTreeSet::new();
+        let mut terms = Vec::new();
+        let mut duplicates = 0;
+        while terms.len() < max_count && duplicates < max_attempts {
+            let term = self.generate_max_look(max_lookbehind);
+            if seen.insert(term.clone()) {
+                terms.push(term);
+                duplicates = 0;
+            } else {
+                duplicates += 1;
+            }
+        }
+        terms
+    }
+
+    /// Like `distinct_terms`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_distinct_terms(
+        &mut self,
+        max_lookbehind: usize,
+        max_count: usize,
+        max_attempts: usize,
+    ) -> Result<Vec<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.distinct_terms(max_lookbehind, max_count, max_attempts))
+    }
+
+    /// Generates up to `num` terms with a custom lookbehind, which are all at least
+    /// `min_distance` edits (inserting, removing or changing one item) away from each other, in
+    /// the order they were generated. A `min_distance` of 1 is the same as `distinct_terms`.
+    ///
+    /// A term which is too close to one it already has is thrown away. It stops once it has `num`
+    /// terms, or after throwing away `max_attempts` terms in a row, so getting fewer than `num`
+    /// terms means the chain probably can't make many more which are that different.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_diverse(
+        &mut self,
+        max_lookbehind: usize,
+        num: usize,
+        min_distance: usize,
+        max_attempts: usize,
+    ) -> Vec<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut terms: Vec<Vec<T>> = Vec::new();
+        let mut rejected = 0;
+        while terms.len() < num && rejected < max_attempts {
+            let term = self.generate_max_look(max_lookbehind);
+            if terms
+                .iter()
+                .all(|other| edit_distance(other, &term) >= min_distance)
+            {
+                terms.push(term);
+                rejected = 0;
+            } else {
+                rejected += 1;
+            }
+        }
+        terms
+    }
+
+    /// Like `generate_diverse`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_diverse(
+        &mut self,
+        max_lookbehind: usize,
+        num: usize,
+        min_distance: usize,
+        max_attempts: usize,
+    ) -> Result<Vec<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_diverse(max_lookbehind, num, min_distance, max_attempts))
+    }
+
+    /// Generates terms with a custom lookbehind until they have `total_tokens` items between
+    /// them, e.g. to fill a space of a fixed size. The last term which goes over is dealt with
+    /// as `overflow` says, and is the last one.
+    ///
+    /// Empty terms are left out, since they don't add any items. It stops early after
+    /// `max_attempts` empty terms in a row, e.g. for a chain which was only trained on empty
+    /// terms.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_budget(
+        &mut self,
+        max_lookbehind: usize,
+        total_tokens: usize,
+        overflow: BudgetOverflow,
+        max_attempts: usize,
+    ) -> Vec<Vec<T>> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut terms = Vec::new();
+        let mut left = total_tokens;
+        let mut empty = 0;
+        while left > 0 && empty < max_attempts {
+            let mut term = self.generate_max_look(max_lookbehind);
+            if term.is_empty() {
+                empty += 1;
+                continue;
+            }
+            empty = 0;
+            if term.len() > left {
+                if overflow == BudgetOverflow::Truncate {
+                    term.truncate(left);
+                    terms.push(term);
+                }
+                break;
+            }
+            left -= term.len();
+            terms.push(term);
+        }
+        terms
+    }
+
+    /// Like `generate_budget`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_budget(
+        &mut self,
+        max_lookbehind: usize,
+        total_tokens: usize,
+        overflow: BudgetOverflow,
+        max_attempts: usize,
+    ) -> Result<Vec<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_budget(max_lookbehind, total_tokens, overflow, max_attempts))
+    }
+
+    /// Like `generate_max_look`, but also returns the random numbers it drew, which `replay` can
+    /// use to make the same term again, without this chain's random number generator.
+    ///
+    /// Each item, and the end of the term, is chosen by drawing a number from 0 up to, but not
+    /// including, the total count of the stage (or of the alphabet, after falling back to it),
+    /// and then going through the next items in order, adding up their counts, until the total
+    /// is more than the number drawn. There is one draw for each item, and one for the end. This
+    /// uses the random number generator exactly as `generate_max_look` does, so it makes the same
+    /// term.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if a
+    /// count is more than `u32::MAX`.
+    pub fn generate_recorded(&mut self, max_lookbehind: usize) -> (Vec<T>, Vec<u32>) {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        match self.recorded(max_lookbehind) {
+            Ok(recorded) => recorded,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like `generate_recorded`, but returns an error if `max_lookbehind` is invalid, this chain
+    /// hasn't been trained, or a number drawn is more than `u32::MAX`.
+    pub fn try_generate_recorded(&mut self, max_lookbehind: usize) -> Result<(Vec<T>, Vec<u32>)> {
+        self.check_can_generate(max_lookbehind)?;
+        self.recorded(max_lookbehind)
+    }
+
+    /// `generate_recorded`, with an error instead of a draw which doesn't fit in a `u32`, which
+    /// ends the term there.
+    fn recorded(&mut self, max_lookbehind: usize) -> Result<(Vec<T>, Vec<u32>)> {
+        let rng = &mut self.rng;
+        let mut draws = Vec::new();
+        let mut too_big = false;
+        let term = tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            if stats.0 == 0 {
+                // An empty alphabet, so the term ends without a draw
+                return None;
+            }
+            let draw = rng.gen_range(0, stats.0);
+            match u32::try_from(draw) {
+                Ok(recorded) => draws.push(recorded),
+                Err(_) => {
+                    too_big = true;
+                    return None;
+                }
+            }
+            Some(weighted_choice_at(&stats.1, draw).unwrap_or_default())
+        })
+        .collect();
+        if too_big {
+            return Err(Error::InvalidArgument("counts are too big to record"));
+        }
+        Ok((term, draws))
+    }
+
+    /// Generates a new term, choosing each item from the longest stage of at most `max_look`
+    /// items which was seen when training, like `generate_max_look`, but never falling back to
+    /// a stage shorter than `min_look`. If there's no such stage, the term ends.
+    ///
+    /// Near the start of a term there are fewer than `min_look` items to look back at, so the
+    /// stage with all of them is used. A chain trained with `train` has every stage this can
+    /// reach, so this only differs from `generate_max_look` when stages are missing, e.g. in a
+    /// chain from `read_csv` or `extract_order`.
+    /// # Panics
+    /// If `min_look` is 0, or greater than `max_look`, or `max_look` is greater than the
+    /// lookbehind this was created with.
+    pub fn generate_order_window(&mut self, min_look: usize, max_look: usize) -> Vec<T> {
+        assert!(1 <= min_look && min_look <= max_look && max_look <= self.size);
+        let mut term = Vec::new();
+        let mut curr = vec![Boundary::Start];
+        loop {
+            let floor = min_look.min(curr.len());
+            let stats = (floor..=curr.len())
+                .rev()
+                .find_map(|len| self.stages.get(&curr[curr.len() - len..]));
+            match stats.map(|stats| weighted_choice_counts(&mut self.rng, stats)) {
+                Some(Boundary::Tok(t)) => {
+                    curr.push(Boundary::Tok(t.clone()));
+                    if curr.len() > max_look {
+                        curr.remove(0);
+                    }
+                    term.push(t);
+                }
+                _ => return term,
+            }
+        }
+    }
+
+    /// Like `generate_order_window`, but returns an error if `min_look` or `max_look` is invalid,
+    /// or this chain hasn't been trained.
+    pub fn try_generate_order_window(
+        &mut self,
+        min_look: usize,
+        max_look: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_look)?;
+        if min_look == 0 || min_look > max_look {
+            return Err(Error::InvalidArgument(
+                "min_look must be between 1 and max_look",
+            ));
+        }
+        Ok(self.generate_order_window(min_look, max_look))
+    }
+
+    /// Generates a new term with a custom lookbehind, where each item which could come next is
+    /// chosen in proportion to `weight(item, count)`, rather than its count. The end of the term
+    /// keeps its count as its weight.
+    ///
+    /// Items with a weight which isn't positive are never chosen. If nothing after a stage has a
+    /// positive weight, it falls back to the shorter stage, and then to the alphabet. If nothing
+    /// is left there either, the term ends.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_with_weight_fn(
+        &mut self,
+        max_lookbehind: usize,
+        weight: impl Fn(&T, usize) -> f64,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let rng = &mut self.rng;
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            let weights: Vec<(&Boundary<T>, f64)> = stats
+                .1
+                .iter()
+                .map(|(next, count)| match next {
+                    Boundary::Tok(t) => (next, weight(t, *count)),
+                    _ => (next, *count as f64),
+                })
+                .collect();
+            choose_by_weight(rng, &weights).cloned()
+        })
+        .collect()
+    }
+
+    /// Like `generate_with_weight_fn`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_with_weight_fn(
+        &mut self,
+        max_lookbehind: usize,
+        weight: impl Fn(&T, usize) -> f64,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_with_weight_fn(max_lookbehind, weight))
+    }
+
+    /// Generates a new term with a custom lookbehind, using the temperature `temps[i]` (see
+    /// `generate_annealed`) to choose item `i`, or the end of the term after `i` items. The last
+    /// temperature is used for the rest of the term. e.g. `&[0.1, 1.]` makes the first item
+    /// almost always the most common first item, and the rest as likely as usual.
+    ///
+    /// Like `generate_annealed`, this stops after `max_len` items.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, or if
+    /// `temps` is empty, or a temperature isn't finite and greater than 0.
+    pub fn generate_positional_temp(
+        &mut self,
+        max_lookbehind: usize,
+        temps: &[f64],
+        max_len: usize,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        assert!(!temps.is_empty(), "temps must not be empty");
+        assert!(
+            temps.iter().all(|temp| valid_temperature(*temp)),
+            "temperatures must be finite and greater than 0"
+        );
+        let rng = &mut self.rng;
+        let mut position = 0;
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            let temperature = temps[position.min(temps.len() - 1)];
+            position += 1;
+            tempered_choice(rng, &stats.1, temperature)
+        })
+        .take(max_len)
+        .collect()
+    }
+
+    /// Like `generate_positional_temp`, but returns an error if `max_lookbehind` or `temps` is
+    /// invalid, or this chain hasn't been trained.
+    pub fn try_generate_positional_temp(
+        &mut self,
+        max_lookbehind: usize,
+        temps: &[f64],
+        max_len: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        if temps.is_empty() || !temps.iter().all(|temp| valid_temperature(*temp)) {
+            return Err(Error::InvalidArgument(
+                "temps must be finite, greater than 0, and not empty",
+            ));
+        }
+        Ok(self.generate_positional_temp(max_lookbehind, temps, max_len))
+    }
+
+    /// Generates a new term with a custom lookbehind, which starts with the first
+    /// `max_lookbehind - 1` items of a term from training, chosen by how many terms started with
+    /// them, and then carries on as usual.
+    ///
+    /// Usually each of the first items is chosen after the ones before it, falling back to
+    /// shorter stages where needed, so this keeps openings exactly as they were seen in training.
+    /// Terms which were shorter than the opening are never chosen from, and if there were no
+    /// terms that long, this is the same as `generate_max_look` with `StartMode::Incremental`.
+    /// `StartMode::Openings` makes `generate_max_look` do this too. The first time for each
+    /// lookbehind, this looks through every stage to find the openings, which are kept until the
+    /// stages change.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_authentic_start(&mut self, max_lookbehind: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let curr = self.choose_opening(max_lookbehind);
+        self.sample_from(curr, max_lookbehind).collect()
+    }
+
+    /// The stage of `max_lookbehind` items at the start of a training term which
+    /// `generate_authentic_start` starts with, or just the start if there are none.
+    fn choose_opening(&mut self, max_lookbehind: usize) -> Vec<Boundary<T>> {
+        if !self.openings.contains_key(&max_lookbehind) {
+            let openings = self.openings(max_lookbehind);
+            self.openings.insert(max_lookbehind, openings);
+        }
+        choose_opening_from(&mut self.rng, &self.openings[&max_lookbehind])
+    }
+
+    /// Like `generate_authentic_start`, but returns an error if `max_lookbehind` is invalid, or
+    /// this chain hasn't been trained.
+    pub fn try_generate_authentic_start(&mut self, max_lookbehind: usize) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_authentic_start(max_lookbehind))
+    }
+
+    /// Generates new terms with a custom lookbehind until `pred` returns true for one, and
+    /// returns it, or `None` if `pred` returned false for `max_attempts` terms.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_until(
+        &mut self,
+        max_lookbehind: usize,
+        pred: impl Fn(&[T]) -> bool,
+        max_attempts: usize,
+    ) -> Option<Vec<T>> {
+        (0..max_attempts)
+            .map(|_| self.generate_max_look(max_lookbehind))
+            .find(|term| pred(term))
+    }
+
+    /// Like `generate_until`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_until(
+        &mut self,
+        max_lookbehind: usize,
+        pred: impl Fn(&[T]) -> bool,
+        max_attempts: usize,
+    ) -> Result<Option<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_until(max_lookbehind, pred, max_attempts))
+    }
+
+    /// Generates a new term with a custom lookbehind, where every item (and the end) had at
+    /// least a `min_token_prob` probability of being chosen.
+    ///
+    /// Terms with a less likely item are thrown away, and `None` is returned if no term was
+    /// found after `max_attempts` attempts.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_min_prob(
+        &mut self,
+        max_lookbehind: usize,
+        min_token_prob: f64,
+        max_attempts: usize,
+    ) -> Option<Vec<T>> {
+        for _ in 0..max_attempts {
+            let term = self.generate_max_look(max_lookbehind);
+            if self
+                .transitions(&term, max_lookbehind)
+                .all(|t| t.probability() >= min_token_prob)
+            {
+                return Some(term);
+            }
+        }
+
+        None
+    }
+
+    /// Like `generate_min_prob`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_min_prob(
+        &mut self,
+        max_lookbehind: usize,
+        min_token_prob: f64,
+        max_attempts: usize,
+    ) -> Result<Option<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_min_prob(max_lookbehind, min_token_prob, max_attempts))
+    }
+
+    /// Generates `n` new terms with a custom lookbehind, and returns the ones which must be
+    /// copies of a term from training, to check whether the chain is memorising its input.
+    ///
+    /// A term counts as memorised when every item after the first `max_lookbehind - 1`, and its
+    /// end, were chosen from a stage of length `max_lookbehind` which was only seen once in
+    /// training. Each of those stages only has one possible next item, which leads to the next
+    /// stage, so together they can only have come from one place in one training term, from its
+    /// start to its end. Terms with fewer than `max_lookbehind - 1` items never count.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn sample_training_like(&mut self, n: usize, max_lookbehind: usize) -> Vec<Vec<T>> {
+        let mut memorised = Vec::new();
+        for _ in 0..n {
+            let term = self.generate_max_look(max_lookbehind);
+            if self.is_memorised(&term, max_lookbehind) {
+                memorised.push(term);
+            }
+        }
+        memorised
+    }
+
+    /// Like `sample_training_like`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_sample_training_like(
+        &mut self,
+        n: usize,
+        max_lookbehind: usize,
+    ) -> Result<Vec<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.sample_training_like(n, max_lookbehind))
+    }
+
+    /// The most likely thing to come after `term`, the start of a term, using the lookbehind
+    /// this was created with. `None` if nothing can come after it, i.e. the chain is untrained.
+    pub fn most_likely_next(&mut self, term: &[T], tie_break: TieBreak) -> Option<NextToken<T>> {
+        let mut curr = self.context_after(term, self.size);
+        let rng = &mut self.rng;
+        choose_next_by(&self.stages, &self.alphabet, &mut curr, &mut |stats| {
+            most_likely(&stats.1, tie_break, rng)
+        })
+        .and_then(NextToken::from_boundary)
+    }
+
+    /// A random thing to come after `term`, the start of a term, chosen in the same way as when
+    /// generating with the lookbehind this was created with. `None` if nothing can come after
+    /// it, i.e. the chain is untrained.
+    pub fn sample_next(&mut self, term: &[T]) -> Option<NextToken<T>> {
+        let mut curr = self.context_after(term, self.size);
+        let rng = &mut self.rng;
+        choose_next_by(&self.stages, &self.alphabet, &mut curr, &mut |stats| {
+            if stats.0 == 0 {
+                None
+            } else {
+                Some(weighted_choice_counts(rng, stats))
+            }
+        })
+        .and_then(NextToken::from_boundary)
+    }
+
+    /// A random item, chosen by how often it was seen in training regardless of position, or
+    /// `None` if this chain hasn't been trained.
+    pub fn sample_token(&mut self) -> Option<T> {
+        if self.alphabet.0 == 0 {
+            None
+        } else {
+            Some(weighted_choice_counts(&mut self.rng, &self.alphabet))
+        }
+    }
+
+    /// Generates a new term with a custom lookbehind, one item at a time.
+    ///
+    /// The iterator ends at the end of the term.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn token_stream(&mut self, max_lookbehind: usize) -> impl Iterator<Item = T> + '_ {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        self.sample_from(vec![Boundary::Start], max_lookbehind)
+    }
+
+    /// Like `token_stream`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_token_stream(
+        &mut self,
+        max_lookbehind: usize,
+    ) -> Result<impl Iterator<Item = T> + '_> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.token_stream(max_lookbehind))
+    }
+
+    /// Generates a new term with a custom lookbehind, one item at a time, like
+    /// `generate_max_look`, which collects this. Unlike `token_stream`, this starts the term as
+    /// the `StartMode` says. With `StartMode::Openings`, the opening is chosen straight away,
+    /// and the rest of the term as the iterator is used.
+    ///
+    /// The iterator ends at the end of the term.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_iter(&mut self, max_lookbehind: usize) -> impl Iterator<Item = T> + '_ {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let curr = match self.start_mode {
+            StartMode::Incremental => vec![Boundary::Start],
+            StartMode::Openings => self.choose_opening(max_lookbehind),
+        };
+        self.sample_from(curr, max_lookbehind)
+    }
+
+    /// Like `generate_iter`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_iter(
+        &mut self,
+        max_lookbehind: usize,
+    ) -> Result<impl Iterator<Item = T> + '_> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_iter(max_lookbehind))
+    }
+
+    /// Like `generate_max_look`, but without the fast path for chains with a lookbehind of 1,
+    /// which makes the same terms. This is only public so `benches/order1.rs` can compare them.
+    #[doc(hidden)]
+    pub fn generate_general_path(&mut self, max_lookbehind: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let curr = match self.start_mode {
+            StartMode::Incremental => vec![Boundary::Start],
+            StartMode::Openings => self.choose_opening(max_lookbehind),
+        };
+        self.sample_by_path(curr, max_lookbehind, false).collect()
+    }
+
+    /// The items of the stage `curr`, and then the rest of a term generated after it.
+    fn sample_from(
+        &mut self,
+        curr: Vec<Boundary<T>>,
+        max_lookbehind: usize,
+    ) -> impl Iterator<Item = T> + '_ {
+        let order1 = self.size == 1;
+        self.sample_by_path(curr, max_lookbehind, order1)
+    }
+
+    /// `sample_from`, with the fast path for a lookbehind of 1 if `order1`.
+    fn sample_by_path(
+        &mut self,
+        curr: Vec<Boundary<T>>,
+        max_lookbehind: usize,
+        order1: bool,
+    ) -> impl Iterator<Item = T> + '_ {
+        let start: Vec<T> = curr
+            .iter()
+            .filter_map(|item| match item {
+                Boundary::Tok(t) => Some(t.clone()),
+                _ => None,
+            })
+            .collect();
+        start.into_iter().chain(sampled_tokens(
+            &self.stages,
+            &self.alphabet,
+            &mut self.cumulative,
+            &mut self.rng,
+            curr,
+            max_lookbehind,
+            order1,
+        ))
+    }
+}
+
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    /// The stages of `max_lookbehind` items at the start of a training term, with how often each
+    /// was seen, and their total.
+    pub(crate) fn openings(&self, max_lookbehind: usize) -> Openings<T> {
+        let openings: BTreeMap<Vec<Boundary<T>>, usize> = self
+            .stages
+            .iter()
+            .filter(|(stage, _)| stage.len() == max_lookbehind && stage[0] == Boundary::Start)
+            .map(|(stage, stats)| (stage.clone(), stats.0))
+            .collect();
+        (openings.values().sum(), openings)
+    }
+
+    /// Moves the training data of this chain into a new chain, which uses `rng` to generate.
+    pub fn with_rng<R2: Rng>(self, rng: R2) -> MarkovChain<T, R2> {
+        MarkovChain {
+            size: self.size,
+            rng,
+            stages: self.stages,
+            alphabet: self.alphabet,
+            lengths: self.lengths,
+            members: self.members,
+            start_mode: self.start_mode,
+            rounding: self.rounding,
+            cumulative: self.cumulative,
+            openings: self.openings,
+        }
+    }
+
+    /// Reserve space for at least `expected_contexts` more stages, to avoid growing the internal
+    /// map many times when training on a large corpus.
+    ///
+    /// Training on a term of `n` items adds at most `size * (n + 1)` stages, so a corpus adds at
+    /// most `size * (number of items + number of terms)`. Most stages are shared between terms,
+    /// so the real number is usually much lower. Training on a sample of the corpus and looking
+    /// at how many stages it made is a better guide.
+    ///
+    /// With the `deterministic` feature, the stages are in a sorted map, which can't reserve
+    /// space, so this does nothing.
+    pub fn reserve(&mut self, expected_contexts: usize) {
+        #[cfg(not(feature = "deterministic"))]
+        self.stages.reserve(expected_contexts);
+        #[cfg(feature = "deterministic")]
+        let _ = expected_contexts;
+    }
+
+    fn record_occurance(&mut self, mut stage: &[Boundary<T>], next: Boundary<T>, times: usize) {
+        while !stage.is_empty() {
+            if !self.cumulative.is_empty() {
+                self.cumulative.remove(stage);
+            }
+            self.openings.clear();
+            // Most stages have been seen before, so look them up without allocating a new key.
+            let stage_stat = match self.stages.get_mut(stage) {
+                Some(stage_stat) => stage_stat,
+                None => self.stages.entry(stage.to_vec()).or_default(),
+            };
+            stage_stat.0 += times;
+            *stage_stat.1.entry(next.clone()).or_default() += times;
+
+            stage = &stage[1..];
+        }
+    }
+
+    /// Teach the markov chain this `term`.
+    pub fn train(&mut self, term: impl Iterator<Item = T>) {
+        let term = std::iter::once(Boundary::Start)
+            .chain(term.map(Boundary::Tok))
+            .chain(std::iter::once(Boundary::End));
+        self.train_with_boundaries(term);
+    }
+
+    /// Teach the markov chain each term in `items`, as if it was trained on it `count` times,
+    /// e.g. from a table of how often each word is used. This is the same as calling `train`
+    /// `count` times for each term, but faster. Terms with a count of 0 are ignored.
+    pub fn train_counted(&mut self, items: impl IntoIterator<Item = (Vec<T>, usize)>) {
+        for (term, count) in items.into_iter().filter(|(_, count)| *count > 0) {
+            let term = std::iter::once(Boundary::Start)
+                .chain(term.into_iter().map(Boundary::Tok))
+                .chain(std::iter::once(Boundary::End))
+                .collect();
+            self.train_times(term, count);
+        }
+    }
+
+    /// Teach the markov chain this `term`, which already includes its boundaries.
+    ///
+    /// `train` is the same as calling this with the term wrapped in `Boundary::Start` and
+    /// `Boundary::End`. Leaving out the `Start` means the chain doesn't learn how the term
+    /// begins, and leaving out the `End` means it doesn't learn that the term can finish there.
+    ///
+    /// # Panics
+    /// If `Boundary::Start` is anywhere but first, or `Boundary::End` anywhere but last.
+    pub fn train_with_boundaries(&mut self, term: impl Iterator<Item = Boundary<T>>) {
+        self.train_times(term.collect(), 1);
+    }
+
+    /// `train_with_boundaries`, as if `term` was trained on `times` times.
+    fn train_times(&mut self, term: Vec<Boundary<T>>, times: usize) {
+        self.count_items(&term, times);
+        self.record_stages(&term, times);
+    }
+
+    /// Adds the items and length of `term` to the alphabet and lengths, `times` times.
+    ///
+    /// # Panics
+    /// If `Boundary::Start` is anywhere but first, or `Boundary::End` anywhere but last.
+    fn count_items(&mut self, term: &[Boundary<T>], times: usize) {
+        for (idx, item) in term.iter().enumerate() {
+            match item {
+                Boundary::Start => assert!(idx == 0, "Boundary::Start must be first"),
+                Boundary::End => assert!(idx == term.len() - 1, "Boundary::End must be last"),
+                Boundary::Tok(t) => {
+                    self.alphabet.0 += times;
+                    *self.alphabet.1.entry(t.clone()).or_default() += times;
+                }
+            }
+        }
+        if term.len() >= 2 && term[0] == Boundary::Start && term[term.len() - 1] == Boundary::End {
+            *self.lengths.entry(term.len() - 2).or_default() += times;
+        }
+    }
+
+    /// Records every stage of `term`, of every length up to the lookbehind, `times` times.
+    fn record_stages(&mut self, term: &[Boundary<T>], times: usize) {
+        for idx in 1..term.len() {
+            for len in 1..(self.size + 1) {
+                if len <= idx {
+                    self.record_occurance(&term[idx - len..idx], term[idx].clone(), times);
+                }
+            }
+        }
+    }
+
+    /// Like `train_with_boundaries`, but returns an error, without learning anything, if
+    /// `Boundary::Start` is anywhere but first, or `Boundary::End` anywhere but last.
+    pub fn try_train_with_boundaries(
+        &mut self,
+        term: impl Iterator<Item = Boundary<T>>,
+    ) -> Result<()> {
+        let term: Vec<Boundary<T>> = term.collect();
+        let misplaced = term.iter().enumerate().any(|(idx, item)| match item {
+            Boundary::Start => idx != 0,
+            Boundary::End => idx != term.len() - 1,
+            Boundary::Tok(_) => false,
+        });
+        if misplaced {
+            return Err(Error::MisplacedBoundary);
+        }
+        self.train_with_boundaries(term.into_iter());
+        Ok(())
+    }
+
+    /// Forget `term`, undoing `train`, e.g. so a term which a user didn't like is less likely to
+    /// be generated again. Every count which training on `term` added is taken away, and stages
+    /// and items which are left with a count of 0 are removed.
+    ///
+    /// Returns false, without changing anything, if something training on `term` adds isn't
+    /// there, so it wasn't trained on. The counts don't remember which terms they came from, so
+    /// a term which wasn't trained on, but is made of the parts of ones which were, is still
+    /// taken away. How often items were seen as a class by `train_with_aliases` isn't changed.
+    pub fn untrain(&mut self, term: impl Iterator<Item = T>) -> bool {
+        let term: Vec<Boundary<T>> = std::iter::once(Boundary::Start)
+            .chain(term.map(Boundary::Tok))
+            .chain(std::iter::once(Boundary::End))
+            .collect();
+        let mut items: BTreeMap<&T, usize> = BTreeMap::new();
+        for item in term.iter() {
+            if let Boundary::Tok(t) = item {
+                *items.entry(t).or_default() += 1;
+            }
+        }
+        // The same occurrences as `train_times` records, where `record_occurance` also records
+        // every shorter stage at the end of each stage
+        let mut occurrences: BTreeMap<(&[Boundary<T>], &Boundary<T>), usize> = BTreeMap::new();
+        for idx in 1..term.len() {
+            for len in 1..=self.size.min(idx) {
+                for start in idx - len..idx {
+                    *occurrences
+                        .entry((&term[start..idx], &term[idx]))
+                        .or_default() += 1;
+                }
+            }
+        }
+
+        let trained = items
+            .iter()
+            .all(|(t, times)| self.alphabet.1.get(*t).is_some_and(|count| count >= times))
+            && occurrences.iter().all(|((stage, next), times)| {
+                self.stages
+                    .get(*stage)
+                    .and_then(|stats| stats.1.get(*next))
+                    .is_some_and(|count| count >= times)
+            });
+        if !trained {
+            return false;
+        }
+
+        for (t, times) in items {
+            self.alphabet.0 -= times;
+            let count = self.alphabet.1.get_mut(t).expect("checked above");
+            *count -= times;
+            if *count == 0 {
+                self.alphabet.1.remove(t);
+            }
+        }
+        for ((stage, next), times) in occurrences {
+            self.cumulative.remove(stage);
+            self.openings.clear();
+            let stats = self.stages.get_mut(stage).expect("checked above");
+            stats.0 -= times;
+            let count = stats.1.get_mut(next).expect("checked above");
+            *count -= times;
+            if *count == 0 {
+                stats.1.remove(next);
+            }
+            if stats.1.is_empty() {
+                self.stages.remove(stage);
+            }
+        }
+        let len = term.len() - 2;
+        if let Some(count) = self.lengths.get_mut(&len) {
+            *count -= 1;
+            if *count == 0 {
+                self.lengths.remove(&len);
+            }
+        }
+        true
+    }
+
+    /// Teach the markov chain `term`, with each item replaced by its class, `alias(item)`, e.g.
+    /// the same digit for every digit, so the chain learns the patterns of the classes from fewer
+    /// examples.
+    ///
+    /// Generating gives the classes, not the items. The chain also remembers how often each item
+    /// was seen as each class, so `unalias` or `generate_unaliased` can turn a class back into an
+    /// item, chosen at random by how often it was seen. Merging chains adds up these counts, like
+    /// the others, but they aren't saved by `write_csv` or `freeze`.
+    pub fn train_with_aliases(&mut self, term: impl Iterator<Item = T>, alias: &impl Fn(&T) -> T) {
+        let members = &mut self.members;
+        let classes: Vec<T> = term
+            .map(|item| {
+                let class = alias(&item);
+                *members
+                    .entry(class.clone())
+                    .or_default()
+                    .entry(item)
+                    .or_default() += 1;
+                class
+            })
+            .collect();
+        self.train(classes.into_iter());
+    }
+
+    /// Teach the markov chain one long, continuous sequence of items, e.g. a whole document,
+    /// without marking where it starts or ends.
+    ///
+    /// This is the same as `train_with_boundaries` with no `Boundary::Start` or `Boundary::End`,
+    /// so the chain learns every transition between neighbouring items, but not how terms begin
+    /// or finish. Generating from a chain only trained like this starts from a random item and
+    /// never reaches an end, so use `token_stream` and take as many items as needed.
+    pub fn train_stream(&mut self, tokens: impl Iterator<Item = T>) {
+        self.train_with_boundaries(tokens.map(Boundary::Tok));
+    }
+
+    /// How often each item was seen in training, regardless of position.
+    ///
+    /// This is much smaller than the whole chain, for when only the item frequencies are needed.
+    /// It can be added to another chain with `import_alphabet_into`.
+    pub fn export_alphabet(&self) -> BTreeMap<T, usize> {
+        self.alphabet.1.clone()
+    }
+
+    /// Each item seen in training, sorted, and how often it was seen, regardless of position.
+    /// Like `export_alphabet`, without copying it.
+    pub fn alphabet(&self) -> impl Iterator<Item = (&T, usize)> + '_ {
+        self.alphabet.1.iter().map(|(t, count)| (t, *count))
+    }
+
+    /// The number of different items seen in training.
+    pub fn alphabet_size(&self) -> usize {
+        self.alphabet.1.len()
+    }
+
+    /// Adds the counts in `alphabet`, e.g. from `export_alphabet`, to how often each item was
+    /// seen in training. This doesn't change the stages, so it only affects choices which fall
+    /// back to the alphabet, like `sample_token`.
+    pub fn import_alphabet_into(&mut self, alphabet: BTreeMap<T, usize>) {
+        for (t, count) in alphabet.into_iter().filter(|(_, count)| *count > 0) {
+            self.alphabet.0 += count;
+            *self.alphabet.1.entry(t).or_default() += count;
+        }
+    }
+
+    /// Adds the counts of `other` to this chain, as if this chain had also been trained on
+    /// everything `other` was, e.g. to combine chains trained on parts of a corpus on different
+    /// threads. `other` can have a different random number generator, or none.
+    ///
+    /// This is `merge_weighted` with weights of 1.
+    /// # Panics
+    /// If the chains have different lookbehinds.
+    pub fn merge<R2>(&mut self, other: &MarkovChain<T, R2>) {
+        assert_eq!(
+            self.size, other.size,
+            "can only merge chains with the same lookbehind"
+        );
+        // The counts are whole, so there's nothing to round
+        self.merge_counts(other, 1., 1., |_, count| count as usize);
+    }
+
+    /// Like `merge`, but returns an error, without changing anything, if the chains have
+    /// different lookbehinds.
+    pub fn try_merge<R2>(&mut self, other: &MarkovChain<T, R2>) -> Result<()> {
+        if self.size != other.size {
+            return Err(Error::SizeMismatch {
+                size: self.size,
+                other_size: other.size,
+            });
+        }
+        self.merge(other);
+        Ok(())
+    }
+
+    /// Blend `other` into this chain, as if this chain had been trained `self_weight` times and
+    /// `other` had been trained `other_weight` times.
+    ///
+    /// Each count becomes `self_weight * count + other_weight * other_count`, rounded with the
+    /// mode from `set_rounding_mode`, which rounds up by default, and anything which rounds to 0
+    /// is removed. The totals are recalculated from the new counts. To round another way just
+    /// this once, use `merge_weighted_with_rounding`.
+    ///
+    /// # Panics
+    /// If the chains have different lookbehinds, or if a weight is negative or not finite.
+    pub fn merge_weighted(&mut self, other: &Self, self_weight: f64, other_weight: f64)
+    where
+        R: Rng,
+    {
+        self.merge_weighted_with_rounding(other, self_weight, other_weight, self.rounding);
+    }
+
+    /// Like `merge_weighted`, but rounding the new counts with `rounding`, rather than the mode
+    /// from `set_rounding_mode`.
+    ///
+    /// # Panics
+    /// If the chains have different lookbehinds, or if a weight is negative or not finite.
+    pub fn merge_weighted_with_rounding(
+        &mut self,
+        other: &Self,
+        self_weight: f64,
+        other_weight: f64,
+        rounding: RoundingMode,
+    ) where
+        R: Rng,
+    {
+        assert_eq!(
+            self.size, other.size,
+            "can only merge chains with the same lookbehind"
+        );
+        assert!(
+            self_weight.is_finite() && self_weight >= 0.,
+            "self_weight must be finite and not negative"
+        );
+        assert!(
+            other_weight.is_finite() && other_weight >= 0.,
+            "other_weight must be finite and not negative"
+        );
+
+        self.merge_counts(other, self_weight, other_weight, |rng, count| {
+            rounding.round(count, rng)
+        });
+    }
+
+    /// The counts of `merge_weighted`, from another chain, which can have a different random
+    /// number generator, rounded with `round`, which is given the random number generator of
+    /// this chain.
+    fn merge_counts<R2>(
+        &mut self,
+        other: &MarkovChain<T, R2>,
+        self_weight: f64,
+        other_weight: f64,
+        mut round: impl FnMut(&mut R, f64) -> usize,
+    ) {
+        let rng = &mut self.rng;
+        let mut round = |count| round(rng, count);
+        self.alphabet = weighted_sum(
+            Some(&self.alphabet.1),
+            Some(&other.alphabet.1),
+            self_weight,
+            other_weight,
+            &mut round,
+        );
+        self.lengths = weighted_sum(
+            Some(&self.lengths),
+            Some(&other.lengths),
+            self_weight,
+            other_weight,
+            &mut round,
+        )
+        .1;
+        let classes: BTreeSet<T> = self
+            .members
+            .keys()
+            .chain(other.members.keys())
+            .cloned()
+            .collect();
+        for class in classes {
+            let members = weighted_sum(
+                self.members.get(&class),
+                other.members.get(&class),
+                self_weight,
+                other_weight,
+                &mut round,
+            )
+            .1;
+            if members.is_empty() {
+                self.members.remove(&class);
+            } else {
+                self.members.insert(class, members);
+            }
+        }
+
+        self.cumulative.clear();
+        self.openings.clear();
+        let stages = &other.stages;
+        let mut stage_keys: Vec<Vec<Boundary<T>>> = self
+            .stages
+            .keys()
+            .chain(stages.keys().filter(|k| !self.stages.contains_key(*k)))
+            .cloned()
+            .collect();
+        // So stochastic rounding draws the same random numbers for the same stages every time
+        stage_keys.sort_unstable();
+        for stage in stage_keys {
+            let stats = weighted_sum(
+                self.stages.get(&stage).map(|stats| &stats.1),
+                stages.get(&stage).map(|stats| &stats.1),
+                self_weight,
+                other_weight,
+                &mut round,
+            );
+            if stats.1.is_empty() {
+                self.stages.remove(&stage);
+            } else {
+                self.stages.insert(stage, stats);
+            }
+        }
+    }
+
+    /// Like `merge_weighted`, but returns an error, without changing anything, if the chains
+    /// have different lookbehinds, or a weight is negative or not finite.
+    pub fn try_merge_weighted(
+        &mut self,
+        other: &Self,
+        self_weight: f64,
+        other_weight: f64,
+    ) -> Result<()>
+    where
+        R: Rng,
+    {
+        self.check_merge(other, self_weight, other_weight)?;
+        self.merge_weighted(other, self_weight, other_weight);
+        Ok(())
+    }
+
+    /// Like `merge_weighted_with_rounding`, but returns an error, without changing anything, if
+    /// the chains have different lookbehinds, or a weight is negative or not finite.
+    pub fn try_merge_weighted_with_rounding(
+        &mut self,
+        other: &Self,
+        self_weight: f64,
+        other_weight: f64,
+        rounding: RoundingMode,
+    ) -> Result<()>
+    where
+        R: Rng,
+    {
+        self.check_merge(other, self_weight, other_weight)?;
+        self.merge_weighted_with_rounding(other, self_weight, other_weight, rounding);
+        Ok(())
+    }
+
+    fn check_merge(&self, other: &Self, self_weight: f64, other_weight: f64) -> Result<()> {
+        if self.size != other.size {
+            return Err(Error::SizeMismatch {
+                size: self.size,
+                other_size: other.size,
+            });
+        }
+        let valid = |weight: f64| weight.is_finite() && weight >= 0.;
+        if !valid(self_weight) || !valid(other_weight) {
+            return Err(Error::InvalidArgument(
+                "weights must be finite and not negative",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Trains this chain on `term`, while forgetting some of what it was trained on before, so
+    /// that it follows recent terms more closely.
+    ///
+    /// Afterwards, `term` makes up `recent_weight` of the chain, and everything it was trained on
+    /// before makes up the rest. If the chain has been trained on `n` terms, the old counts are
+    /// multiplied by `1 - recent_weight`, and `term` is counted `recent_weight * n` times, so the
+    /// chain still counts as `n` terms, and repeated updates don't make the counts grow. e.g. a
+    /// `recent_weight` of 0.01 means the last 100 or so terms matter most. Counts are rounded
+    /// with the mode from `set_rounding_mode`, like `merge_weighted`. By default they're rounded
+    /// up, so rare items are never forgotten. Rounding to the nearest integer instead means very
+    /// small weights, or chains trained on few terms, can round `term` away completely. An
+    /// untrained chain is just trained on `term`.
+    ///
+    /// # Panics
+    /// If `recent_weight` isn't between 0 and 1.
+    pub fn update(&mut self, term: impl Iterator<Item = T>, recent_weight: f64)
+    where
+        R: Rng,
+    {
+        self.update_with_rounding(term, recent_weight, self.rounding);
+    }
+
+    /// Like `update`, but rounding the new counts with `rounding`, rather than the mode from
+    /// `set_rounding_mode`. Rounding down forgets old items faster, and rounding up never forgets
+    /// them, but makes their counts stop getting smaller at 1.
+    ///
+    /// # Panics
+    /// If `recent_weight` isn't between 0 and 1.
+    pub fn update_with_rounding(
+        &mut self,
+        term: impl Iterator<Item = T>,
+        recent_weight: f64,
+        rounding: RoundingMode,
+    ) where
+        R: Rng,
+    {
+        assert!(
+            (0. ..=1.).contains(&recent_weight),
+            "recent_weight must be between 0 and 1"
+        );
+        let num_terms = self
+            .stages
+            .get([Boundary::Start].as_slice())
+            .map_or(0, |stats| stats.0);
+
+        if num_terms == 0 {
+            self.train(term);
+            return;
+        }
+        let mut recent: MarkovChain<T, ()> = MarkovChain::without_rng(self.size);
+        recent.train(term);
+        self.merge_counts(
+            &recent,
+            1. - recent_weight,
+            recent_weight * num_terms as f64,
+            |rng, count| rounding.round(count, rng),
+        );
+    }
+
+    /// Like `update`, but returns an error, without changing anything, if `recent_weight` isn't
+    /// between 0 and 1.
+    pub fn try_update(&mut self, term: impl Iterator<Item = T>, recent_weight: f64) -> Result<()>
+    where
+        R: Rng,
+    {
+        self.try_update_with_rounding(term, recent_weight, self.rounding)
+    }
+
+    /// Like `update_with_rounding`, but returns an error, without changing anything, if
+    /// `recent_weight` isn't between 0 and 1.
+    pub fn try_update_with_rounding(
+        &mut self,
+        term: impl Iterator<Item = T>,
+        recent_weight: f64,
+        rounding: RoundingMode,
+    ) -> Result<()>
+    where
+        R: Rng,
+    {
+        if !(0. ..=1.).contains(&recent_weight) {
+            return Err(Error::InvalidArgument(
+                "recent_weight must be between 0 and 1",
+            ));
+        }
+        self.update_with_rounding(term, recent_weight, rounding);
+        Ok(())
+    }
+
+    /// Rounds every count to one of `buckets` values, to make the chain smaller once it's saved,
+    /// at the cost of less precise probabilities.
+    ///
+    /// The buckets are spaced logarithmically between 1 and the largest count, so small counts,
+    /// where a difference of 1 matters most, are kept most precisely. Each count becomes a
+    /// representative value from inside its bucket, the geometric mean of its edges, rounded
+    /// with the mode from `set_rounding_mode` and kept inside the bucket, so the order of counts
+    /// never changes and no count becomes 0, whatever the mode. The alphabet is rounded the same
+    /// way, and the totals are recalculated.
+    ///
+    /// # Panics
+    /// If `buckets` is 0.
+    pub fn quantize(&mut self, buckets: usize)
+    where
+        R: Rng,
+    {
+        assert!(buckets > 0, "buckets must be more than 0");
+        let max = self
+            .stages
+            .values()
+            .flat_map(|stats| stats.1.values())
+            .chain(self.alphabet.1.values())
+            .max()
+            .copied()
+            .unwrap_or(0);
+        let bucket_of = |count: usize| -> usize {
+            let bucket = (count as f64).ln() / ((max + 1) as f64).ln() * buckets as f64;
+            (bucket as usize).min(buckets - 1)
+        };
+        let edge = |bucket: usize| ((max + 1) as f64).powf(bucket as f64 / buckets as f64);
+        let values: Vec<usize> = (0..buckets)
+            .map(|bucket| {
+                let (low, high) = (edge(bucket), edge(bucket + 1));
+                let value = self.rounding.round((low * high).sqrt(), &mut self.rng);
+                // Buckets narrower than 1 might not have any counts in them
+                let lowest = low.ceil() as usize;
+                value.clamp(lowest, ((high.ceil() as usize).max(2) - 1).max(lowest))
+            })
+            .collect();
+        let quantized = |count: &mut usize| *count = values[bucket_of(*count)];
+
+        self.alphabet.1.values_mut().for_each(quantized);
+        self.alphabet.0 = self.alphabet.1.values().sum();
+        self.cumulative.clear();
+        self.openings.clear();
+        for stats in self.stages.values_mut() {
+            stats.1.values_mut().for_each(quantized);
+            stats.0 = stats.1.values().sum();
+        }
+    }
+
+    /// Like `quantize`, but returns an error, without changing anything, if `buckets` is 0.
+    pub fn try_quantize(&mut self, buckets: usize) -> Result<()>
+    where
+        R: Rng,
+    {
+        if buckets == 0 {
+            return Err(Error::InvalidArgument("buckets must be more than 0"));
+        }
+        self.quantize(buckets);
+        Ok(())
+    }
+
+    /// Groups of stages which have exactly the same probabilities for what comes next, e.g.
+    /// because one is always followed by the other. Each group is sorted, and the groups are
+    /// sorted by their first stage. Stages which aren't the same as any other are left out.
+    pub fn equivalent_context_groups(&self) -> Vec<Vec<Vec<Boundary<T>>>> {
+        let mut groups: BTreeMap<Vec<(&Boundary<T>, usize)>, Vec<Vec<Boundary<T>>>> =
+            BTreeMap::new();
+        for (stage, stats) in self.stages.iter() {
+            // Counts in the same ratio are the same probabilities
+            let divisor = stats.1.values().copied().fold(0, gcd);
+            let key = stats
+                .1
+                .iter()
+                .map(|(next, count)| (next, count / divisor))
+                .collect();
+            groups.entry(key).or_default().push(stage.clone());
+        }
+
+        let mut groups: Vec<Vec<Vec<Boundary<T>>>> = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect();
+        for group in groups.iter_mut() {
+            group.sort();
+        }
+        groups.sort();
+        groups
+    }
+
+    /// Removes every stage which has probabilities for what comes next within `tolerance` of the
+    /// shorter stage generating would fall back to without it, and returns how many stages were
+    /// removed. This makes the chain smaller, especially for very regular training data.
+    ///
+    /// The distance between two stages is the biggest difference between the probabilities they
+    /// give anything to come next. With a `tolerance` of 0, only stages which are exactly the same
+    /// as their fall back are removed, so generating with any lookbehind is unchanged, apart from
+    /// using the random number generator differently. Otherwise, the probability of each choice
+    /// when generating changes by at most `tolerance`. Stages of 1 item are never removed, since
+    /// they would fall back to the alphabet, which can't end a term.
+    ///
+    /// The counts of the removed stages are lost, so methods which look at the stages, like
+    /// `stats` or `kl_divergence`, will give different answers.
+    ///
+    /// # Panics
+    /// If `tolerance` is negative or not finite.
+    pub fn collapse_equivalent(&mut self, tolerance: f64) -> usize {
+        assert!(
+            tolerance.is_finite() && tolerance >= 0.,
+            "tolerance must be finite and not negative"
+        );
+        // Shorter stages first, so each stage is compared with what it would really fall back to
+        let mut stages: Vec<Vec<Boundary<T>>> = self
+            .stages
+            .keys()
+            .filter(|stage| stage.len() > 1)
+            .cloned()
+            .collect();
+        stages.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+
+        let mut removed = 0;
+        for stage in stages {
+            let equivalent = (1..stage.len())
+                .find_map(|start| self.stages.get(&stage[start..]))
+                .is_some_and(|fallback| distance(&self.stages[&stage], fallback) <= tolerance);
+            if equivalent {
+                self.stages.remove(&stage);
+                self.cumulative.remove(&stage);
+                self.openings.clear();
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Like `collapse_equivalent`, but returns an error, without changing anything, if
+    /// `tolerance` is negative or not finite.
+    pub fn try_collapse_equivalent(&mut self, tolerance: f64) -> Result<usize> {
+        if !tolerance.is_finite() || tolerance < 0. {
+            return Err(Error::InvalidArgument(
+                "tolerance must be finite and not negative",
+            ));
+        }
+        Ok(self.collapse_equivalent(tolerance))
+    }
+
+    /// Removes everything which was seen after a stage fewer than `min_count` times, and the
+    /// stages left with nothing after them, and returns how many next items were removed. This
+    /// makes the chain smaller, by dropping rare transitions, which are often just noise in a big
+    /// corpus.
+    ///
+    /// The alphabet isn't changed, so items which were only seen in rare transitions can still
+    /// be chosen after falling back to it, see `unreachable_tokens`.
+    pub fn prune(&mut self, min_count: usize) -> usize {
+        let mut removed = 0;
+        let mut emptied = Vec::new();
+        for (stage, stats) in self.stages.iter_mut() {
+            let before = stats.1.len();
+            stats.1.retain(|_, count| *count >= min_count);
+            if stats.1.len() == before {
+                continue;
+            }
+            removed += before - stats.1.len();
+            stats.0 = stats.1.values().sum();
+            self.cumulative.remove(stage);
+            self.openings.clear();
+            if stats.1.is_empty() {
+                emptied.push(stage.clone());
+            }
+        }
+        for stage in emptied {
+            self.stages.remove(&stage);
+        }
+        removed
+    }
+
+    /// Checks that the counts of this chain agree with each other, which is always true for the
+    /// chains made by this crate, however they're trained or changed, and which generating
+    /// relies on.
+    ///
+    /// Every stage must have between 1 and the lookbehind items, with the start of the term only
+    /// first and never the end, and something seen after it, but never the start. Every count
+    /// must be more than 0, and every total the sum of its counts. Returns
+    /// `Error::Inconsistent` saying what's wrong otherwise.
+    pub fn validate(&self) -> Result<()> {
+        for (stage, stats) in self.stages.iter() {
+            if stage.is_empty() || stage.len() > self.size {
+                return Err(Error::Inconsistent("a stage has the wrong length"));
+            }
+            if stage.iter().skip(1).any(|item| *item == Boundary::Start)
+                || stage.contains(&Boundary::End)
+            {
+                return Err(Error::Inconsistent("a stage has a misplaced boundary"));
+            }
+            if stats.1.is_empty() {
+                return Err(Error::Inconsistent("nothing was seen after a stage"));
+            }
+            if stats.1.contains_key(&Boundary::Start) {
+                return Err(Error::Inconsistent("the start was seen after a stage"));
+            }
+            if stats.1.values().any(|count| *count == 0) {
+                return Err(Error::Inconsistent("a stage has a count of 0"));
+            }
+            if stats.0 != stats.1.values().sum::<usize>() {
+                return Err(Error::Inconsistent("a stage has the wrong total"));
+            }
+        }
+        if self.alphabet.1.values().any(|count| *count == 0) {
+            return Err(Error::Inconsistent("the alphabet has a count of 0"));
+        }
+        if self.alphabet.0 != self.alphabet.1.values().sum::<usize>() {
+            return Err(Error::Inconsistent("the alphabet has the wrong total"));
+        }
+        Ok(())
+    }
+
+    /// Returns true if this chain cannot generate a useful variety of terms.
+    ///
+    /// A chain is degenerate when any of these hold:
+    /// * it has not been trained on any non-empty term, so it can only produce empty terms,
+    /// * it has seen fewer than 2 distinct items, so terms can only vary in length,
+    /// * no stage has more than one possible next item (e.g. when trained on one term with no
+    ///   repeated items), so it always generates the same term.
+    pub fn is_degenerate(&self) -> bool {
+        let only_empty = self
+            .stages
+            .get(&vec![Boundary::Start])
+            .is_none_or(|stats| stats.1.keys().all(|k| *k == Boundary::End));
+
+        only_empty
+            || self.alphabet.1.len() < 2
+            || self.stages.values().all(|stats| stats.1.len() == 1)
+    }
+
+    /// The mean number of distinct next items for each stage, or 0 if untrained.
+    pub fn average_branching(&self) -> f64 {
+        mean_branching(self.stages.values())
+    }
+
+    /// The mean number of distinct next items for each stage of length `order`, or 0 if there
+    /// are no stages of that length. The start of a term counts towards the length of a stage.
+    pub fn branching_at_order(&self, order: usize) -> f64 {
+        mean_branching(
+            self.stages
+                .iter()
+                .filter(|(stage, _)| stage.len() == order)
+                .map(|(_, stats)| stats),
+        )
+    }
+
+    /// A new chain with a lookbehind of `order`, which only has the stages of exactly that
+    /// length, and the same alphabet and random number generator as this chain.
+    ///
+    /// The start of a term counts towards the length of a stage, like `branching_at_order`.
+    /// Generating from the new chain only ever uses stages of length `order`, falling back to
+    /// the alphabet when there isn't one, so it shows what that order contributes on its own.
+    ///
+    /// # Panics
+    /// If `order` is 0 or greater than the lookbehind this was created with.
+    pub fn extract_order(&self, order: usize) -> MarkovChain<T, R>
+    where
+        R: Clone,
+    {
+        assert!(order >= 1 && order <= self.size);
+        let stages = self
+            .stages
+            .iter()
+            .filter(|(stage, _)| stage.len() == order)
+            .map(|(stage, stats)| (stage.clone(), stats.clone()))
+            .collect();
+        let alphabet = self.alphabet.1.clone();
+        MarkovChain {
+            size: order,
+            rng: self.rng.clone(),
+            stages,
+            alphabet: (alphabet.values().sum(), alphabet),
+            lengths: self.lengths.clone(),
+            members: self.members.clone(),
+            start_mode: self.start_mode,
+            rounding: self.rounding,
+            cumulative: StageMap::new(),
+            openings: BTreeMap::new(),
+        }
+    }
+
+    /// Like `extract_order`, but returns an error if `order` is 0 or greater than the lookbehind
+    /// this was created with.
+    pub fn try_extract_order(&self, order: usize) -> Result<MarkovChain<T, R>>
+    where
+        R: Clone,
+    {
+        self.check_lookbehind(order)?;
+        Ok(self.extract_order(order))
+    }
+
+    /// A new chain which only knows about the items in `keep`, with the same lookbehind and
+    /// random number generator as this chain, e.g. to only generate ASCII letters.
+    ///
+    /// Stages which have any other item are removed, as are the other items after the stages
+    /// which are left, and from the alphabet, and then the totals are worked out again. So
+    /// generating from the new chain never gives an item which isn't in `keep`. Stages with
+    /// nothing left after them are removed too. The lengths of the training terms are kept as
+    /// they were, as are the members of the classes from `train_with_aliases` which are kept.
+    pub fn subset_to_tokens(&self, keep: &BTreeSet<T>) -> MarkovChain<T, R>
+    where
+        R: Clone,
+    {
+        let kept = |item: &Boundary<T>| match item {
+            Boundary::Tok(t) => keep.contains(t),
+            _ => true,
+        };
+        let stages = self
+            .stages
+            .iter()
+            .filter(|(stage, _)| stage.iter().all(kept))
+            .filter_map(|(stage, stats)| {
+                let next: BTreeMap<Boundary<T>, usize> = stats
+                    .1
+                    .iter()
+                    .filter(|(next, _)| kept(next))
+                    .map(|(next, count)| (next.clone(), *count))
+                    .collect();
+                (!next.is_empty()).then(|| (stage.clone(), (next.values().sum(), next)))
+            })
+            .collect();
+        let alphabet: BTreeMap<T, usize> = self
+            .alphabet
+            .1
+            .iter()
+            .filter(|(t, _)| keep.contains(t))
+            .map(|(t, count)| (t.clone(), *count))
+            .collect();
+        MarkovChain {
+            size: self.size,
+            rng: self.rng.clone(),
+            stages,
+            alphabet: (alphabet.values().sum(), alphabet),
+            lengths: self.lengths.clone(),
+            members: self
+                .members
+                .iter()
+                .filter(|(class, _)| keep.contains(class))
+                .map(|(class, members)| (class.clone(), members.clone()))
+                .collect(),
+            start_mode: self.start_mode,
+            rounding: self.rounding,
+            cumulative: StageMap::new(),
+            openings: BTreeMap::new(),
+        }
+    }
+
+    /// How different the next items in `other` are from this chain: the mean Kullback-Leibler
+    /// divergence (in nats) of the next items of `other` from the next items of this chain, for
+    /// each stage which was seen by both chains.
+    ///
+    /// To avoid infinities, both distributions are smoothed by adding 1 to the count of every
+    /// next item seen after that stage by either chain (Laplace smoothing). Stages which were only
+    /// seen by one of the chains are ignored, and if no stages were seen by both, this is NaN.
+    pub fn kl_divergence(&self, other: &Self) -> f64 {
+        let mut sum = 0.;
+        let mut num_shared = 0;
+        for (stage, p) in self.stages.iter() {
+            let q = match other.stages.get(stage) {
+                None => continue,
+                Some(q) => q,
+            };
+            let next: BTreeSet<&Boundary<T>> = p.1.keys().chain(q.1.keys()).collect();
+            let p_total = (p.0 + next.len()) as f64;
+            let q_total = (q.0 + next.len()) as f64;
+            sum += next
+                .into_iter()
+                .map(|item| {
+                    let p = (p.1.get(item).copied().unwrap_or(0) + 1) as f64 / p_total;
+                    let q = (q.1.get(item).copied().unwrap_or(0) + 1) as f64 / q_total;
+                    p * (p / q).ln()
+                })
+                .sum::<f64>();
+            num_shared += 1;
+        }
+
+        sum / num_shared as f64
+    }
+
+    /// The number of stages which were seen by both this chain and `other`. Both chains should
+    /// have the same lookbehind, otherwise only the stages up to the shorter one can be shared.
+    pub fn common_contexts(&self, other: &Self) -> usize {
+        self.stages
+            .keys()
+            .filter(|stage| other.stages.contains_key(*stage))
+            .count()
+    }
+
+    /// The number of stages which were seen by this chain, but not by `other`. Together with
+    /// `common_contexts`, this is every stage of this chain.
+    pub fn unique_contexts(&self, other: &Self) -> usize {
+        self.stages.len() - self.common_contexts(other)
+    }
+
+    /// Returns true if something was seen after `context` in training, anywhere apart from the
+    /// start of a term, without falling back to shorter stages. Contexts longer than the
+    /// lookbehind this was created with, and the empty context, never were.
+    pub fn has_context(&self, context: &[T]) -> bool {
+        let context: Vec<Boundary<T>> = context.iter().cloned().map(Boundary::Tok).collect();
+        self.stages.contains_key(&context)
+    }
+
+    /// Returns true if `term` could be generated by this chain, using the lookbehind this was
+    /// created with.
+    pub fn can_generate(&self, term: &[T]) -> bool {
+        self.transitions(term, self.size).all(|t| t.count > 0)
+    }
+
+    /// The fraction of the terms in `vocab` which could be generated by this chain (see
+    /// `can_generate`), or NaN if `vocab` is empty.
+    pub fn coverage(&self, vocab: &[Vec<T>]) -> f64 {
+        let covered = vocab.iter().filter(|term| self.can_generate(term)).count();
+        covered as f64 / vocab.len() as f64
+    }
+
+    /// Whether `term` must be a copy of a training term, see `sample_training_like`.
+    fn is_memorised(&self, term: &[T], max_lookbehind: usize) -> bool {
+        if term.len() + 1 < max_lookbehind {
+            return false;
+        }
+        self.transitions(term, max_lookbehind)
+            .skip(max_lookbehind - 1)
+            .all(|t| t.order == max_lookbehind && t.total == 1)
+    }
+
+    /// The smallest lookbehind which could generate `term`, or `None` if no lookbehind up to the
+    /// one this was created with could.
+    ///
+    /// Shorter stages back off in the same way as generating does, so for a chain which was only
+    /// trained with `train`, a term which can be generated at all can always be generated with a
+    /// lookbehind of 1. Higher answers come from chains which are missing shorter stages, e.g.
+    /// from `extract_order` or `read_csv`.
+    pub fn min_sufficient_order(&self, term: &[T]) -> Option<usize> {
+        (1..=self.size).find(|order| self.transitions(term, *order).all(|t| t.count > 0))
+    }
+
+    /// The least likely step of generating `term` with the lookbehind this was created with, as
+    /// its position and its surprisal, the negative natural log of its probability, or `None`
+    /// if `term` is empty. The position is of an item of `term`, or `term.len()` for its end.
+    ///
+    /// The surprisal is infinite for a step which the chain can't make. For ties, this is the
+    /// first of them.
+    pub fn most_surprising(&self, term: &[T]) -> Option<(usize, f64)> {
+        if term.is_empty() {
+            return None;
+        }
+        self.transitions(term, self.size)
+            .map(|transition| -transition.probability().ln())
+            .enumerate()
+            .fold(None, |worst, (pos, surprisal)| match worst {
+                Some((_, most)) if most >= surprisal => worst,
+                _ => Some((pos, surprisal)),
+            })
+    }
+
+    /// The natural log of the probability of generating `term`, and then ending it, with the
+    /// lookbehind this was created with, e.g. to sort candidate terms by how typical they are.
+    ///
+    /// Each step falls back to shorter stages, and the alphabet, in the same way as generating
+    /// does. This is negative infinity if `term` can't be generated.
+    pub fn score(&self, term: impl Iterator<Item = T>) -> f64 {
+        let term: Vec<T> = term.collect();
+        self.transitions(&term, self.size)
+            .map(|transition| transition.probability().ln())
+            .sum()
+    }
+
+    /// Which lookbehinds generating `term` would use, as pairs of the lookbehind and the
+    /// fraction of the items of `term`, and its end, which would be chosen with it, sorted by
+    /// lookbehind. Lookbehinds which wouldn't be used are left out.
+    ///
+    /// This is the length of the stage each item would be chosen from, after falling back to
+    /// shorter stages in the same way as generating does, where 0 means from the alphabet. Mostly
+    /// high lookbehinds mean `term` follows the training data closely, while low ones mean it's
+    /// made of common parts which were seen in many places.
+    pub fn attribution(&self, term: &[T]) -> Vec<(usize, f64)> {
+        let mut orders: BTreeMap<usize, usize> = BTreeMap::new();
+        for transition in self.transitions(term, self.size) {
+            *orders.entry(transition.order).or_default() += 1;
+        }
+        let num_transitions = term.len() + 1;
+        orders
+            .into_iter()
+            .map(|(order, count)| (order, count as f64 / num_transitions as f64))
+            .collect()
+    }
+
+    /// The length of training term which `percentile` percent of training terms are no longer
+    /// than (using the nearest rank), or `None` if no training terms had both boundaries.
+    /// # Panics
+    /// If `percentile` isn't between 0 and 100.
+    fn length_percentile(&self, percentile: f64) -> Option<usize> {
+        assert!(
+            (0. ..=100.).contains(&percentile),
+            "percentile must be between 0 and 100"
+        );
+        let num_terms: usize = self.lengths.values().sum();
+        let rank = ((percentile / 100. * num_terms as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        self.lengths.iter().find_map(|(len, count)| {
+            seen += count;
+            (seen >= rank).then_some(*len)
+        })
+    }
+
+    /// The items in the alphabet which no stage has after it, sorted. Generating can only give
+    /// these items by falling back to the alphabet, e.g. after changing the stages, as in
+    /// `extract_order`, leaves them out. This is empty for chains which were only trained.
+    pub fn unreachable_tokens(&self) -> BTreeSet<T> {
+        let mut unreachable: BTreeSet<T> = self.alphabet.1.keys().cloned().collect();
+        for stats in self.stages.values() {
+            for next in stats.1.keys() {
+                if let Boundary::Tok(t) = next {
+                    unreachable.remove(t);
+                }
+            }
+        }
+        unreachable
+    }
+
+    /// The stages where the only thing which was seen next is the end of the term, sorted.
+    /// Generating always ends at these stages, unless it's generating with a shorter lookbehind.
+    pub fn dead_end_contexts(&self) -> Vec<Vec<Boundary<T>>> {
+        let mut dead_ends: Vec<Vec<Boundary<T>>> = self
+            .stages
+            .iter()
+            .filter(|(_, stats)| stats.1.keys().all(|next| *next == Boundary::End))
+            .map(|(stage, _)| stage.clone())
+            .collect();
+        dead_ends.sort();
+        dead_ends
+    }
+
+    /// The stages which generating with the lookbehind this was created with can use, sorted.
+    /// Other stages are only used when generating with a shorter lookbehind, and stages which
+    /// aren't in either can be removed without changing generated terms.
+    pub fn reachable_contexts(&self) -> Vec<Vec<Boundary<T>>> {
+        let mut reachable: BTreeSet<Vec<Boundary<T>>> = BTreeSet::new();
+        let mut seen: BTreeSet<Vec<Boundary<T>>> = BTreeSet::new();
+        let mut todo = vec![vec![Boundary::Start]];
+        while let Some(mut curr) = todo.pop() {
+            if !seen.insert(curr.clone()) {
+                continue;
+            }
+            let counts = self.next_counts(&mut curr);
+            if self.stages.contains_key(&curr) {
+                reachable.insert(curr.clone());
+            }
+            for next in counts.1.keys() {
+                if let Boundary::Tok(_) = next {
+                    let mut after = curr.clone();
+                    after.push(next.clone());
+                    if after.len() > self.size {
+                        after.remove(0);
+                    }
+                    todo.push(after);
+                }
+            }
+        }
+
+        reachable.into_iter().collect()
+    }
+
+    /// The probability of each thing which can come after `term`, the start of a term, using the
+    /// lookbehind this was created with, and falling back to shorter stages in the same way as
+    /// generating does. Empty if nothing can come after it, i.e. the chain is untrained.
+    pub fn predict_next(&self, term: &[T]) -> BTreeMap<NextToken<T>, f64> {
+        let mut curr = self.context_after(term, self.size);
+        let counts = self.next_counts(&mut curr);
+        counts
+            .1
+            .iter()
+            .filter_map(|(next, count)| {
+                let next = NextToken::from_boundary(next.clone())?;
+                Some((next, *count as f64 / counts.0 as f64))
+            })
+            .collect()
+    }
+
+    /// The probability of each thing seen after the stage `context` in training, or `None` if
+    /// `context` was never seen, e.g. to inspect what the chain has learnt. Unlike `predict_next`,
+    /// this doesn't fall back to shorter stages.
+    ///
+    /// A stage at the start of a term begins with `Boundary::Start`, so `[Boundary::Start]` gives
+    /// the probabilities of the first item, and `[Boundary::Tok('a')]` is for after an `'a'`
+    /// anywhere in a term, including at the start. The probabilities add up to 1.
+    pub fn probabilities(&self, context: &[Boundary<T>]) -> Option<BTreeMap<Boundary<T>, f64>> {
+        let stats = self.stages.get(context)?;
+        Some(
+            stats
+                .1
+                .iter()
+                .map(|(next, count)| (next.clone(), *count as f64 / stats.0 as f64))
+                .collect(),
+        )
+    }
+
+    /// For a chain with a lookbehind of 1, every item, sorted, and the probability of each thing
+    /// coming after each item, as a matrix with a row for each item. Column `j` of a row is the
+    /// probability of item `j` coming next, and there's one more column at the end, for the end
+    /// of the term. Each row adds up to 1. Items which nothing was seen after fall back to the
+    /// alphabet, like generating does.
+    ///
+    /// Returns an error if the lookbehind isn't 1.
+    pub fn transition_matrix(&self) -> Result<(Vec<T>, Vec<Vec<f64>>)> {
+        if self.size != 1 {
+            return Err(Error::InvalidArgument(
+                "transition_matrix needs a chain with a lookbehind of 1",
+            ));
+        }
+        let items: Vec<T> = self
+            .alphabet
+            .1
+            .keys()
+            .chain(self.stages.iter().flat_map(|(stage, stats)| {
+                stage.iter().chain(stats.1.keys()).filter_map(|b| match b {
+                    Boundary::Tok(t) => Some(t),
+                    _ => None,
+                })
+            }))
+            .collect::<BTreeSet<&T>>()
+            .into_iter()
+            .cloned()
+            .collect();
+        let column: BTreeMap<&T, usize> = items.iter().enumerate().map(|(i, t)| (t, i)).collect();
+
+        let matrix = items
+            .iter()
+            .map(|t| {
+                let mut row = vec![0.; items.len() + 1];
+                let counts = self.next_counts(&mut vec![Boundary::Tok(t.clone())]);
+                for (next, count) in counts.1.iter() {
+                    let j = match next {
+                        Boundary::Tok(next) => column[next],
+                        _ => items.len(),
+                    };
+                    row[j] = *count as f64 / counts.0 as f64;
+                }
+                row
+            })
+            .collect();
+        Ok((items, matrix))
+    }
+
+    /// How likely it is that one of the `k` most likely things which can come after `term`, the
+    /// start of a term, comes next, between 0 and 1, using the same stage as `predict_next`. The
+    /// end of the term counts as one of the things. `None` if nothing can come after it, i.e. the
+    /// chain is untrained.
+    ///
+    /// Close to 1 for a small `k` means generating is fairly predictable after `term`.
+    pub fn topk_mass(&self, term: &[T], k: usize) -> Option<f64> {
+        let mut curr = self.context_after(term, self.size);
+        let counts = self.next_counts(&mut curr);
+        if counts.0 == 0 {
+            return None;
+        }
+        let mut sorted: Vec<usize> = counts.1.values().copied().collect();
+        sorted.sort_unstable_by(|a, b| b.cmp(a));
+        let top: usize = sorted.iter().take(k).sum();
+        Some(top as f64 / counts.0 as f64)
+    }
+
+    /// How each item of `term`, and then its end, would be chosen when generating with
+    /// `max_lookbehind`, falling back to shorter stages in the same way as generating does.
+    fn transitions<'a>(
+        &'a self,
+        term: &'a [T],
+        max_lookbehind: usize,
+    ) -> impl Iterator<Item = Transition> + 'a {
+        let mut curr: Vec<Boundary<T>> = vec![Boundary::Start];
+        let mut items = term
+            .iter()
+            .cloned()
+            .map(Boundary::Tok)
+            .chain(std::iter::once(Boundary::End));
+
+        std::iter::from_fn(move || {
+            let next = items.next()?;
+            let transition = loop {
+                match self.stages.get(&curr) {
+                    Some(stats) => {
+                        break Transition {
+                            order: curr.len(),
+                            count: stats.1.get(&next).copied().unwrap_or(0),
+                            total: stats.0,
+                        };
+                    }
+                    None if curr.len() == 1 => {
+                        let count = match &next {
+                            Boundary::Tok(t) => self.alphabet.1.get(t).copied().unwrap_or(0),
+                            _ => 0,
+                        };
+                        break Transition {
+                            order: 0,
+                            count,
+                            total: self.alphabet.0,
+                        };
+                    }
+                    None => {
+                        curr.remove(0);
+                    }
+                }
+            };
+
+            curr.push(next);
+            while curr.len() > max_lookbehind {
+                curr.remove(0);
+            }
+            Some(transition)
+        })
+    }
+
+    /// The most likely term of at most `max_len` items, using a custom lookbehind.
+    ///
+    /// Unlike `generate_greedy`, this finds the term with the highest overall probability, by
+    /// keeping the most likely way to get to each stage after each number of items (the Viterbi
+    /// algorithm). This takes `O(max_len * stages * next items)` time, so `max_len` is needed to
+    /// keep it bounded, as well as to stop it looping forever. Returns an empty term if no term
+    /// can end within `max_len` items.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn most_probable_term(&self, max_lookbehind: usize, max_len: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+
+        let mut best: Option<(f64, Vec<T>)> = None;
+        let mut layer: BTreeMap<Vec<Boundary<T>>, (f64, Vec<T>)> = BTreeMap::new();
+        layer.insert(vec![Boundary::Start], (0., Vec::new()));
+
+        for len in 0..=max_len {
+            let mut next_layer: BTreeMap<Vec<Boundary<T>>, (f64, Vec<T>)> = BTreeMap::new();
+            for (mut curr, (log_prob, term)) in layer {
+                let counts = self.next_counts(&mut curr);
+                for (next, count) in counts.1.iter() {
+                    let log_prob = log_prob + (*count as f64 / counts.0 as f64).ln();
+                    match next {
+                        Boundary::Tok(t) if len < max_len => {
+                            let mut stage = curr.clone();
+                            stage.push(next.clone());
+                            if stage.len() > max_lookbehind {
+                                stage.remove(0);
+                            }
+                            if next_layer.get(&stage).is_none_or(|(lp, _)| log_prob > *lp) {
+                                let mut term = term.clone();
+                                term.push(t.clone());
+                                next_layer.insert(stage, (log_prob, term));
+                            }
+                        }
+                        Boundary::End if best.as_ref().is_none_or(|(lp, _)| log_prob > *lp) => {
+                            best = Some((log_prob, term.clone()));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            layer = next_layer;
+        }
+
+        best.map(|(_, term)| term).unwrap_or_default()
+    }
+
+    /// Like `most_probable_term`, but returns an error if `max_lookbehind` is invalid.
+    pub fn try_most_probable_term(&self, max_lookbehind: usize, max_len: usize) -> Result<Vec<T>> {
+        self.check_lookbehind(max_lookbehind)?;
+        Ok(self.most_probable_term(max_lookbehind, max_len))
+    }
+
+    /// How many different terms of at most `max_len` items generating with a custom lookbehind
+    /// could make, or `u128::MAX` if there are more than that.
+    ///
+    /// This counts the ways to get to each stage after each number of items, like
+    /// `most_probable_term`, so it takes `O(max_len * stages * next items)` time, without making
+    /// the terms. `max_len` is needed because a chain which has seen an item after itself, or
+    /// any other loop, can make infinitely many terms.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn count_possible_terms(&self, max_lookbehind: usize, max_len: usize) -> u128 {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+
+        let mut num_terms: u128 = 0;
+        let mut layer: BTreeMap<Vec<Boundary<T>>, u128> = BTreeMap::new();
+        layer.insert(vec![Boundary::Start], 1);
+
+        for len in 0..=max_len {
+            let mut next_layer: BTreeMap<Vec<Boundary<T>>, u128> = BTreeMap::new();
+            for (mut curr, ways) in layer {
+                let counts = self.next_counts(&mut curr);
+                for next in counts.1.keys() {
+                    match next {
+                        Boundary::Tok(_) if len < max_len => {
+                            let mut stage = curr.clone();
+                            stage.push(next.clone());
+                            if stage.len() > max_lookbehind {
+                                stage.remove(0);
+                            }
+                            let stage_ways = next_layer.entry(stage).or_default();
+                            *stage_ways = stage_ways.saturating_add(ways);
+                        }
+                        Boundary::End => num_terms = num_terms.saturating_add(ways),
+                        _ => {}
+                    }
+                }
+            }
+            layer = next_layer;
+        }
+
+        num_terms
+    }
+
+    /// Like `count_possible_terms`, but returns an error if `max_lookbehind` is invalid.
+    pub fn try_count_possible_terms(&self, max_lookbehind: usize, max_len: usize) -> Result<u128> {
+        self.check_lookbehind(max_lookbehind)?;
+        Ok(self.count_possible_terms(max_lookbehind, max_len))
+    }
+
+    /// The mean length of the terms generated with a custom lookbehind, worked out from the
+    /// probabilities in the chain rather than by generating terms.
+    ///
+    /// This follows how likely each stage is after each number of items, adding up how likely
+    /// the term is to end there. A chain with cycles can make terms of any length, so this is an
+    /// approximation: it stops once there's less than a one in a billion chance of the term
+    /// still going, or after 1000 items, and counts any term still going as ending there. So a
+    /// chain which can never end, e.g. one only trained with `train_stream`, gives 1000.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn expected_length(&self, max_lookbehind: usize) -> f64 {
+        const MAX_ITEMS: usize = 1000;
+        const MIN_PROBABILITY: f64 = 1e-9;
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+
+        let mut expected = 0.;
+        let mut still_going = 1.;
+        let mut len = 0;
+        let mut layer: StageMap<T, f64> = StageMap::new();
+        layer.insert(vec![Boundary::Start], 1.);
+
+        while len < MAX_ITEMS && still_going >= MIN_PROBABILITY {
+            let mut next_layer: StageMap<T, f64> = StageMap::new();
+            for (mut curr, prob) in layer {
+                let counts = self.next_counts(&mut curr);
+                for (next, count) in counts.1.iter() {
+                    let prob = prob * *count as f64 / counts.0 as f64;
+                    if *next == Boundary::End {
+                        expected += prob * len as f64;
+                        continue;
+                    }
+                    let mut stage = curr.clone();
+                    stage.push(next.clone());
+                    if stage.len() > max_lookbehind {
+                        stage.remove(0);
+                    }
+                    *next_layer.entry(stage).or_default() += prob;
+                }
+            }
+            layer = next_layer;
+            still_going = layer.values().sum();
+            len += 1;
+        }
+
+        expected + still_going * len as f64
+    }
+
+    /// Like `expected_length`, but returns an error if `max_lookbehind` is invalid.
+    pub fn try_expected_length(&self, max_lookbehind: usize) -> Result<f64> {
+        self.check_lookbehind(max_lookbehind)?;
+        Ok(self.expected_length(max_lookbehind))
+    }
+
+    /// How often each item came after `curr`, from the longest stage that was seen when
+    /// training, or from the alphabet if no stage was seen. The front of `curr` is removed when
+    /// falling back to a shorter stage, like `choose_next_by`.
+    fn next_counts(&self, curr: &mut Vec<Boundary<T>>) -> Cow<'_, Counts<T>> {
+        loop {
+            if let Some(stats) = self.stages.get(curr) {
+                return Cow::Borrowed(stats);
+            }
+            if curr.len() == 1 {
+                break;
+            }
+            curr.remove(0);
+        }
+        Cow::Owned(alphabet_counts(&self.alphabet))
+    }
+
+    /// The stage, before falling back, after generating `term` with `max_lookbehind`.
+    fn context_after(&self, term: &[T], max_lookbehind: usize) -> Vec<Boundary<T>> {
+        let mut curr: Vec<Boundary<T>> = std::iter::once(Boundary::Start)
+            .chain(term.iter().cloned().map(Boundary::Tok))
+            .collect();
+        if curr.len() > max_lookbehind {
+            curr.drain(..curr.len() - max_lookbehind);
+        }
+        curr
+    }
+
+    /// Generates the `n`th term, with a custom lookbehind.
+    ///
+    /// This doesn't use the random number generator of this chain, instead it makes a new one
+    /// from `n`. So for the same training data, the same `n` always gives the same term, which
+    /// allows listing generated terms page by page.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn nth(&self, n: u64, max_lookbehind: usize) -> Vec<T> {
+        self.generate_with_rng(&mut seeded_rng(n), max_lookbehind)
+    }
+
+    /// Like `nth`, but returns an error if `max_lookbehind` is invalid, or this chain hasn't been
+    /// trained.
+    pub fn try_nth(&self, n: u64, max_lookbehind: usize) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.nth(n, max_lookbehind))
+    }
+
+    /// Generates the term for `key`, with a custom lookbehind, so the same key always gives the
+    /// same term for the same training data, e.g. a name for each order number.
+    ///
+    /// This is `nth` of the 64 bit FNV-1a hash of the UTF-8 bytes of `key`, which is the same on
+    /// every platform.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_from_key(&self, key: &str, max_lookbehind: usize) -> Vec<T> {
+        self.nth(fnv1a(key.as_bytes()), max_lookbehind)
+    }
+
+    /// Like `generate_from_key`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_from_key(&self, key: &str, max_lookbehind: usize) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_from_key(key, max_lookbehind))
+    }
+
+    /// Makes the term which `generate_recorded` made with `draws`, using the same training data
+    /// and `max_lookbehind`. The term ends early if `draws` runs out.
+    ///
+    /// With other training data, or another lookbehind, the term is some other term which the
+    /// chain could make. A draw which is too big for its stage chooses the last next item.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn replay(&self, draws: &[u32], max_lookbehind: usize) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let mut draws = draws.iter();
+        tokens_by(&self.stages, &self.alphabet, max_lookbehind, |stats| {
+            if stats.0 == 0 {
+                return None;
+            }
+            let draw = *draws.next()?;
+            Some(weighted_choice_at(&stats.1, draw as usize).unwrap_or_default())
+        })
+        .collect()
+    }
+
+    /// Like `replay`, but returns an error if `max_lookbehind` is invalid, or this chain hasn't
+    /// been trained.
+    pub fn try_replay(&self, draws: &[u32], max_lookbehind: usize) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.replay(draws, max_lookbehind))
+    }
+
+    /// How many distinct items are in the terms `0` to `samples - 1` (see `nth`) generated with a
+    /// custom lookbehind, which can be fewer than the items in the alphabet if some are never
+    /// reached when generating.
+    ///
+    /// This is an estimate from sampling, so it can only be too low: an item which is in a
+    /// fraction `p` of terms is missed with probability `(1 - p)^samples`. More samples make rare
+    /// items less likely to be missed, but take longer. Using `nth` means the same chain and
+    /// `samples` always give the same answer.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn effective_vocab_size(&self, max_lookbehind: usize, samples: usize) -> usize {
+        let items: BTreeSet<T> = (0..samples as u64)
+            .flat_map(|n| self.nth(n, max_lookbehind))
+            .collect();
+        items.len()
+    }
+
+    /// Like `effective_vocab_size`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_effective_vocab_size(&self, max_lookbehind: usize, samples: usize) -> Result<usize> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.effective_vocab_size(max_lookbehind, samples))
+    }
+
+    /// Generates a new term with a custom lookbehind, using `rng` rather than the random number
+    /// generator of this chain.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_with_rng<R2: Rng + ?Sized>(
+        &self,
+        rng: &mut R2,
+        max_lookbehind: usize,
+    ) -> Vec<T> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        generate_term(&self.stages, &self.alphabet, rng, max_lookbehind)
+    }
+
+    /// Like `generate_with_rng`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_with_rng<R2: Rng + ?Sized>(
+        &self,
+        rng: &mut R2,
+        max_lookbehind: usize,
+    ) -> Result<Vec<T>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_with_rng(rng, max_lookbehind))
+    }
+
+    fn check_lookbehind(&self, max_lookbehind: usize) -> Result<()> {
+        if max_lookbehind >= 1 && max_lookbehind <= self.size {
+            Ok(())
+        } else {
+            Err(Error::InvalidLookbehind {
+                max_lookbehind,
+                size: self.size,
+            })
+        }
+    }
+
+    /// Whether there's anything to generate from, for the `try_` versions of the ways to
+    /// generate, which return `Error::Untrained` rather than an empty term. Generating falls back
+    /// to the alphabet when there's no stage, so with no alphabet, any stage after the first item
+    /// would be a surprise, and only the start needs a stage.
+    fn check_can_generate(&self, max_lookbehind: usize) -> Result<()> {
+        self.check_lookbehind(max_lookbehind)?;
+        if self.alphabet.0 == 0 && !self.stages.contains_key([Boundary::Start].as_slice()) {
+            Err(Error::Untrained)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<R> MarkovChain<char, R>
+where
+    R: Rng,
+{
+    /// Generates a new term with a custom lookbehind, as a `String`.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_string(&mut self, max_lookbehind: usize) -> String {
+        self.generate_collect(max_lookbehind)
+    }
+
+    /// Like `generate_string`, but returns an error if `max_lookbehind` is invalid, or this chain
+    /// hasn't been trained.
+    pub fn try_generate_string(&mut self, max_lookbehind: usize) -> Result<String> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_string(max_lookbehind))
+    }
+
+    /// Like `generate_string`, but with the first character in upper case, e.g. for names from a
+    /// chain which was trained on lower case words. An empty term stays empty.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_capitalized(&mut self, max_lookbehind: usize) -> String {
+        capitalize(&self.generate_string(max_lookbehind))
+    }
+
+    /// Like `generate_capitalized`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_capitalized(&mut self, max_lookbehind: usize) -> Result<String> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_capitalized(max_lookbehind))
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug + Send + Sync,
+{
+    /// Generates `count` new terms in parallel, with a custom lookbehind.
+    ///
+    /// Term `i` is the same as `nth(base_seed + i, max_lookbehind)`, so the result only depends
+    /// on the training data and `base_seed`, and not on the number of threads.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn generate_many_parallel(
+        &self,
+        count: usize,
+        max_lookbehind: usize,
+        base_seed: u64,
+    ) -> Vec<Vec<T>> {
+        use rayon::prelude::*;
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+
+        // Only the training data is shared between threads, not the random number generator
+        let (stages, alphabet) = (&self.stages, &self.alphabet);
+        (0..count)
+            .into_par_iter()
+            .map(|i| {
+                let mut rng = seeded_rng(base_seed.wrapping_add(i as u64));
+                generate_term(stages, alphabet, &mut rng, max_lookbehind)
+            })
+            .collect()
+    }
+
+    /// Like `generate_many_parallel`, but returns an error if `max_lookbehind` is invalid, or this
+    /// chain hasn't been trained.
+    pub fn try_generate_many_parallel(
+        &self,
+        count: usize,
+        max_lookbehind: usize,
+        base_seed: u64,
+    ) -> Result<Vec<Vec<T>>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.generate_many_parallel(count, max_lookbehind, base_seed))
+    }
+}
+
+fn check_size(size: usize) -> Result<()> {
+    if size == 0 {
+        Err(Error::ZeroSize)
+    } else {
+        Ok(())
+    }
+}
+
+/// The 64 bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A random number generator which will always produce the same output for `seed`.
+fn seeded_rng(seed: u64) -> rand::prng::ChaChaRng {
+    use rand::SeedableRng;
+    rand::prng::ChaChaRng::seed_from_u64(seed)
+}
+
+/// One step of generating a term.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transition {
+    /// The length of the stage used, or 0 if it fell back to the alphabet
+    order: usize,
+    /// How often the next item was seen after this stage
+    count: usize,
+    /// How often anything was seen after this stage
+    total: usize,
+}
+
+impl Transition {
+    /// How likely this step was.
+    fn probability(&self) -> f64 {
+        if self.total == 0 {
+            0.
+        } else {
+            self.count as f64 / self.total as f64
+        }
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The biggest difference between the probabilities `a` and `b` give anything to come next.
+fn distance<T: Ord>(a: &Counts<T>, b: &Counts<T>) -> f64 {
+    let probability = |counts: &Counts<T>, next: &Boundary<T>| {
+        counts.1.get(next).copied().unwrap_or(0) as f64 / counts.0 as f64
+    };
+    a.1.keys()
+        .chain(b.1.keys())
+        .map(|next| (probability(a, next) - probability(b, next)).abs())
+        .fold(0., f64::max)
+}
+
+/// `a_weight * a + b_weight * b` for each key, rounded with `round`, with zero counts removed,
+/// and the new total.
+fn weighted_sum<K: Ord + Clone>(
+    a: Option<&BTreeMap<K, usize>>,
+    b: Option<&BTreeMap<K, usize>>,
+    a_weight: f64,
+    b_weight: f64,
+    round: &mut impl FnMut(f64) -> usize,
+) -> (usize, BTreeMap<K, usize>) {
+    let mut weights: BTreeMap<K, f64> = BTreeMap::new();
+    for (counts, weight) in [(a, a_weight), (b, b_weight)] {
+        for (key, count) in counts.into_iter().flatten() {
+            *weights.entry(key.clone()).or_default() += *count as f64 * weight;
+        }
+    }
+
+    let counts: BTreeMap<K, usize> = weights
+        .into_iter()
+        .map(|(key, weight)| (key, round(weight)))
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    (counts.values().sum(), counts)
+}
+
+/// The Levenshtein distance between `a` and `b`, the fewest items which need to be inserted,
+/// removed or changed to turn one into the other.
+fn edit_distance<T: Eq>(a: &[T], b: &[T]) -> usize {
+    // The distances from the start of `a` so far to each start of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let changed = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = changed.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn mean_branching<'a, T: 'a>(stats: impl Iterator<Item = &'a Counts<T>>) -> f64 {
+    let (num_stages, num_next) = stats.fold((0, 0), |(num_stages, num_next), stats| {
+        (num_stages + 1, num_next + stats.1.len())
+    });
+    if num_stages == 0 {
+        0.
+    } else {
+        num_next as f64 / num_stages as f64
+    }
+}
+
+/// Chooses the item which comes after `curr` with `choose`, from the longest stage that was seen
+/// when training, or from the alphabet if no stage was seen. If `choose` returns `None` for a
+/// stage, the next shorter one is tried, and `None` is returned if it's `None` for all of them.
+/// The front of `curr` is removed when falling back to a shorter stage.
+fn choose_next_by<T, F>(
+    stages: &Stages<T>,
+    alphabet: &Alphabet<T>,
+    curr: &mut Vec<Boundary<T>>,
+    choose: &mut F,
+) -> Option<Boundary<T>>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    F: FnMut(&Counts<T>) -> Option<Boundary<T>>,
+{
+    loop {
+        if let Some(next) = stages.get(curr).and_then(&mut *choose) {
+            return Some(next);
+        }
+        if curr.len() == 1 {
+            break;
+        }
+        curr.remove(0);
+    }
+
+    choose(&alphabet_counts(alphabet))
+}
+
+/// One of `openings` (see `MarkovChain::openings`), chosen by how often it was seen, or just the
+/// start if there are none.
+fn choose_opening_from<T, R>(rng: &mut R, openings: &Openings<T>) -> Vec<Boundary<T>>
+where
+    T: Clone + Debug,
+    R: Rng + ?Sized,
+{
+    match openings.0 {
+        0 => vec![Boundary::Start],
+        _ => weighted_choice_counts(rng, openings),
+    }
+}
+
+/// The alphabet as the counts of a stage which every item can come after.
+fn alphabet_counts<T: Ord + Clone>(alphabet: &Alphabet<T>) -> Counts<T> {
+    (
+        alphabet.0,
+        alphabet
+            .1
+            .iter()
+            .map(|(t, count)| (Boundary::Tok(t.clone()), *count))
+            .collect(),
+    )
+}
+
+fn generate_term<T, R>(
+    stages: &Stages<T>,
+    alphabet: &Alphabet<T>,
+    rng: &mut R,
+    max_lookbehind: usize,
+) -> Vec<T>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng + ?Sized,
+{
+    tokens(stages, alphabet, rng, max_lookbehind).collect()
+}
+
+/// The items of a new term, chosen one at a time.
+fn tokens<'a, T, R>(
+    stages: &'a Stages<T>,
+    alphabet: &'a Alphabet<T>,
+    rng: &'a mut R,
+    max_lookbehind: usize,
+) -> impl Iterator<Item = T> + 'a
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng + ?Sized,
+{
+    tokens_by(stages, alphabet, max_lookbehind, move |stats| {
+        Some(weighted_choice_counts(rng, stats))
+    })
+}
+
+/// Like `tokens_from`, but choosing from each stage with a binary search of its entry in
+/// `cumulative`, which is added if it isn't there, rather than going through its next items.
+/// This draws the same random numbers, and makes the same choices, as `tokens_from`.
+///
+/// If `order1`, `curr` is only ever the last item, since the lookbehind is 1, so it's looked up
+/// once each time, with no shorter stages to fall back to, and replaced rather than shifted.
+fn sampled_tokens<'a, T, R>(
+    stages: &'a Stages<T>,
+    alphabet: &'a Alphabet<T>,
+    cumulative: &'a mut Cumulative<T>,
+    rng: &'a mut R,
+    mut curr: Vec<Boundary<T>>,
+    max_lookbehind: usize,
+    order1: bool,
+) -> impl Iterator<Item = T> + 'a
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng + ?Sized,
+{
+    let mut finished = false;
+
+    std::iter::from_fn(move || {
+        if finished {
+            return None;
+        }
+        let from_alphabet = |rng: &mut R| match alphabet.0 {
+            0 => Boundary::End,
+            _ => Boundary::Tok(weighted_choice_counts(rng, alphabet)),
+        };
+        let next = if order1 {
+            // Once the running totals of a stage are kept, they're all that's needed to choose
+            // from it, so that's one lookup for each item, rather than one for its stage too.
+            match cumulative.get(&curr) {
+                Some(sums) => cumulative_draw(sums, rng),
+                None => match stages.get(&curr) {
+                    Some(stats) => cumulative_choice(cumulative, &curr, stats, rng),
+                    None => from_alphabet(rng),
+                },
+            }
+        } else {
+            // Fall back to shorter stages, and then the alphabet, like `choose_next_by`
+            loop {
+                if let Some(stats) = stages.get(&curr) {
+                    break cumulative_choice(cumulative, &curr, stats, rng);
+                }
+                if curr.len() == 1 {
+                    break from_alphabet(rng);
+                }
+                curr.remove(0);
+            }
+        };
+        match next {
+            Boundary::End => {
+                finished = true;
+                None
+            }
+            Boundary::Start => unreachable!("the start is never recorded as a next item"),
+            Boundary::Tok(t) if order1 => {
+                curr[0] = Boundary::Tok(t.clone());
+                Some(t)
+            }
+            Boundary::Tok(t) => {
+                curr.push(Boundary::Tok(t.clone()));
+                while curr.len() > max_lookbehind {
+                    curr.remove(0);
+                }
+                Some(t)
+            }
+        }
+    })
+}
+
+/// Like `weighted_choice_counts`, for `stats` of `stage`, using its entry in `cumulative`.
+fn cumulative_choice<T, R>(
+    cumulative: &mut Cumulative<T>,
+    stage: &[Boundary<T>],
+    stats: &Counts<T>,
+    rng: &mut R,
+) -> Boundary<T>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng + ?Sized,
+{
+    if stats.0 == 0 {
+        return Boundary::default();
+    }
+    let draw = rng.gen_range(0, stats.0);
+    if !cumulative.contains_key(stage) {
+        let mut total = 0;
+        let sums = stats
+            .1
+            .iter()
+            .map(|(next, count)| {
+                total += count;
+                (total, next.clone())
+            })
+            .collect();
+        cumulative.insert(stage.to_vec(), sums);
+    }
+    cumulative_pick(&cumulative[stage], draw)
+}
+
+/// Like `cumulative_choice`, for a stage whose running totals, `sums`, are already kept. The
+/// last running total is the total count of the stage.
+fn cumulative_draw<T, R>(sums: &[(usize, Boundary<T>)], rng: &mut R) -> Boundary<T>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng + ?Sized,
+{
+    match sums.last() {
+        Some((total, _)) => cumulative_pick(sums, rng.gen_range(0, *total)),
+        None => Boundary::default(),
+    }
+}
+
+/// The next item which `draw` chooses from the running totals `sums`.
+fn cumulative_pick<T: Clone + Default>(sums: &[(usize, Boundary<T>)], draw: usize) -> Boundary<T> {
+    let idx = sums.partition_point(|(total, _)| *total <= draw);
+    sums.get(idx)
+        .or(sums.last())
+        .map_or_else(Boundary::default, |(_, next)| next.clone())
+}
+
+/// The items of a new term, each chosen with `choose` (see `choose_next_by`). The term ends when
+/// `choose` picks the end, or nothing.
+fn tokens_by<'a, T, F>(
+    stages: &'a Stages<T>,
+    alphabet: &'a Alphabet<T>,
+    max_lookbehind: usize,
+    choose: F,
+) -> impl Iterator<Item = T> + 'a
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    F: FnMut(&Counts<T>) -> Option<Boundary<T>> + 'a,
+{
+    tokens_from(
+        stages,
+        alphabet,
+        vec![Boundary::Start],
+        max_lookbehind,
+        choose,
+    )
+}
+
+/// Like `tokens_by`, but carrying on from the stage `curr`, rather than the start of a term.
+fn tokens_from<'a, T, F>(
+    stages: &'a Stages<T>,
+    alphabet: &'a Alphabet<T>,
+    mut curr: Vec<Boundary<T>>,
+    max_lookbehind: usize,
+    mut choose: F,
+) -> impl Iterator<Item = T> + 'a
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    F: FnMut(&Counts<T>) -> Option<Boundary<T>> + 'a,
+{
+    let mut finished = false;
+
+    std::iter::from_fn(move || {
+        if finished {
+            return None;
+        }
+        match choose_next_by(stages, alphabet, &mut curr, &mut choose) {
+            // we're at end
+            None | Some(Boundary::End) => {
+                finished = true;
+                None
+            }
+            Some(Boundary::Start) => unreachable!("the start is never recorded as a next item"),
+            Some(Boundary::Tok(t)) => {
+                curr.push(Boundary::Tok(t.clone()));
+                while curr.len() > max_lookbehind {
+                    curr.remove(0);
+                }
+                Some(t)
+            }
+        }
+    })
+}
+
+fn valid_temperature(temperature: f64) -> bool {
+    temperature.is_finite() && temperature > 0.
+}
+
+/// A random key from `counts`, where each count `c` is weighted as `c^(1 / temperature)`, or
+/// `None` if `counts` is empty. The weights are worked out relative to the largest count, so
+/// they don't overflow at low temperatures.
+fn tempered_choice<K: Clone, R: Rng + ?Sized>(
+    rng: &mut R,
+    counts: &BTreeMap<K, usize>,
+    temperature: f64,
+) -> Option<K> {
+    let max = (*counts.values().max()? as f64).ln();
+    let weights: Vec<(&K, f64)> = counts
+        .iter()
+        .map(|(key, count)| (key, (((*count as f64).ln() - max) / temperature).exp()))
+        .collect();
+    choose_by_weight(rng, &weights).cloned()
+}
+
+/// A random key from `weights`, in proportion to its weight. Weights which aren't positive, or
+/// are NaN, are never chosen, and if there are no positive weights this is `None`.
+fn choose_by_weight<K: Clone, R: Rng + ?Sized>(rng: &mut R, weights: &[(K, f64)]) -> Option<K> {
+    let positive = || weights.iter().filter(|(_, weight)| *weight > 0.);
+    let total: f64 = positive().map(|(_, weight)| weight).sum();
+    if total <= 0. {
+        return None;
+    }
+    let mut random_number = rng.gen::<f64>() * total;
+    for (key, weight) in positive() {
+        if random_number < *weight {
+            return Some(key.clone());
+        }
+        random_number -= weight;
+    }
+
+    // Rounding can leave a tiny amount over
+    positive().last().map(|(key, _)| key.clone())
+}
+
+/// The most common key in `counts`, with ties settled by `tie_break`.
+fn most_likely<K: Clone, R: Rng + ?Sized>(
+    counts: &BTreeMap<K, usize>,
+    tie_break: TieBreak,
+    rng: &mut R,
+) -> Option<K> {
+    let max = *counts.values().max()?;
+    let mut best = counts
+        .iter()
+        .filter(|(_, count)| **count == max)
+        .map(|(key, _)| key);
+    let chosen = match tie_break {
+        TieBreak::First => best.next(),
+        TieBreak::Last => best.last(),
+        TieBreak::Random => {
+            let best: Vec<&K> = best.collect();
+            Some(best[rng.gen_range(0, best.len())])
+        }
+    };
+    chosen.cloned()
+}
+
+/// `term` with the first character in upper case, like `MarkovChain::generate_capitalized` makes
+/// them. Some characters become more than one, e.g. `ß` becomes `SS`.
+///
+/// ```
+/// assert_eq!(warkov::capitalize("brunda"), "Brunda");
+/// assert_eq!(warkov::capitalize(""), "");
+/// ```
+pub fn capitalize(term: &str) -> String {
+    let mut chars = term.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Chooses one of the keys of `items` at random, with a probability proportional to its count,
+/// in the same way generating chooses the next item. Keys with a count of 0 are never chosen.
+/// `None` if there's nothing to choose, because `items` is empty or every count is 0.
+///
+/// This goes through `items` to add up the counts, and again to find the choice, since there
+/// are no running totals kept for a map which is passed in. To choose from the same counts many
+/// times, keep running totals and use a binary search, like `generate_max_look` does.
+///
+/// ```
+/// use std::collections::BTreeMap;
+///
+/// let items = BTreeMap::from([("heads", 1), ("tails", 3)]);
+/// let choice = warkov::weighted_choice(&mut rand::thread_rng(), &items);
+/// assert!(choice == Some("heads") || choice == Some("tails"));
+/// ```
+pub fn weighted_choice<T: Clone, R: Rng + ?Sized>(
+    rng: &mut R,
+    items: &BTreeMap<T, usize>,
+) -> Option<T> {
+    match items.values().sum() {
+        0 => None,
+        total => weighted_choice_at(items, rng.gen_range(0, total)),
+    }
+}
+
+/// Like `weighted_choice`, for counts with their total already worked out, as the stages store
+/// them, and the default if there's nothing to choose.
+fn weighted_choice_counts<T: Debug + Clone + Default, R: Rng + ?Sized>(
+    rng: &mut R,
+    options: &(usize, BTreeMap<T, usize>),
+) -> T {
+    debug_assert_eq!(options.0, options.1.values().sum::<usize>());
+    if options.0 == 0 {
+        // Nothing to choose, e.g. falling back to an empty alphabet, which for the next item ends
+        // the term
+        return T::default();
+    }
+    weighted_choice_at(&options.1, rng.gen_range(0, options.0)).unwrap_or_default()
+}
+
+/// The choice `weighted_choice` makes when the random number it draws is `random_number`. If
+/// `random_number` is at least the total, it's the last key, and if there are no keys, `None`.
+fn weighted_choice_at<T: Clone>(options: &BTreeMap<T, usize>, random_number: usize) -> Option<T> {
+    let mut curr_value = 0;
+    let mut last_key = None;
+    for (key, value) in options.iter() {
+        last_key = Some(key);
+        if random_number >= curr_value && random_number < curr_value + value {
+            return Some(key.clone());
+        }
+        curr_value += value;
+    }
+
+    last_key.cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+
+    fn stage(k: &str) -> Vec<Boundary<char>> {
+        k.chars().map(Boundary::Tok).collect()
+    }
+
+    fn has_key<R>(mc: &MarkovChain<char, R>, k: &str) -> bool {
+        mc.has_context(&k.chars().collect::<Vec<_>>())
+    }
+
+    fn has_key_w_none_prefix<R>(mc: &MarkovChain<char, R>, k: &str) -> bool {
+        let mut k = stage(k);
+        k.insert(0, Boundary::Start);
+        mc.stages.contains_key(&k)
+    }
+
+    fn has_key_w_none_predict<R>(mc: &MarkovChain<char, R>, k: &str) -> bool {
+        mc.stages
+            .get(&stage(k))
+            .map(|stats| stats.1.contains_key(&Boundary::End))
+            .unwrap_or(false)
+    }
+
+    #[test]
+    fn simple1() {
+        let mut mc = MarkovChain::new(2);
+
+        mc.train("abc".chars());
+        //assert_eq!(mc.stages.len(), 4, "{:?}", mc.stages);
+        assert!(mc.stages.contains_key(&vec![Boundary::Start]));
+        assert!(has_key_w_none_prefix(&mc, "a"));
+        assert!(!has_key_w_none_prefix(&mc, "ab"));
+        assert!(!has_key_w_none_prefix(&mc, "abc"));
+
+        assert!(has_key_w_none_predict(&mc, "c"));
+        assert!(has_key_w_none_predict(&mc, "bc"));
+        assert!(!has_key_w_none_predict(&mc, "abc"));
+
+        assert!(has_key(&mc, "ab"));
+        assert!(has_key(&mc, "ab"));
+        assert!(has_key(&mc, "bc"));
+        assert!(!has_key(&mc, "ac"));
+
+        assert!(has_key(&mc, "a"));
+        assert!(has_key(&mc, "b"));
+        assert!(has_key(&mc, "c"));
+        assert!(!has_key(&mc, "d"));
+    }
+
+    #[test]
+    fn simple2() {
+        let mut mc = MarkovChain::new(3);
+
+        mc.train("abc".chars());
+        assert!(mc.stages.contains_key(&vec![Boundary::Start]));
+        assert!(has_key_w_none_prefix(&mc, "a"));
+        assert!(has_key_w_none_prefix(&mc, "ab"));
+        assert!(!has_key_w_none_prefix(&mc, "abc"));
+
+        assert!(has_key(&mc, "abc"));
+
+        assert!(has_key(&mc, "ab"));
+        assert!(has_key(&mc, "ab"));
+        assert!(has_key(&mc, "bc"));
+        assert!(!has_key(&mc, "ac"));
+
+        assert!(has_key(&mc, "a"));
+        assert!(has_key(&mc, "b"));
+        assert!(has_key(&mc, "c"));
+        assert!(!has_key(&mc, "d"));
+
+        mc.train("rust".chars());
+        assert!(mc.stages.contains_key(&vec![Boundary::Start]));
+        assert!(has_key_w_none_prefix(&mc, "r"));
+        assert!(has_key_w_none_prefix(&mc, "ru"));
+        assert!(!has_key_w_none_prefix(&mc, "rus"));
+        assert!(!has_key_w_none_prefix(&mc, "rust"));
+        assert!(has_key(&mc, "r"));
+        assert!(has_key(&mc, "u"));
+        assert!(has_key(&mc, "s"));
+        assert!(has_key(&mc, "t"));
+        assert!(has_key(&mc, "ru"));
+        assert!(has_key(&mc, "us"));
+        assert!(has_key(&mc, "st"));
+        assert!(has_key(&mc, "rus"));
+        assert!(has_key(&mc, "ust"));
+        assert!(!has_key(&mc, "rust"));
+    }
+
+    #[test]
+    fn start_and_end_are_distinct() {
+        let mut mc = MarkovChain::new(2);
+        mc.train("a".chars());
+
+        let start = &mc.stages[&vec![Boundary::Start]];
+        assert_eq!(
+            start.1.keys().collect::<Vec<_>>(),
+            vec![&Boundary::Tok('a')]
+        );
+        let after_a = &mc.stages[&vec![Boundary::Start, Boundary::Tok('a')]];
+        assert_eq!(after_a.1.keys().collect::<Vec<_>>(), vec![&Boundary::End]);
+
+        // Neither boundary is ever learned as part of the term itself
+        assert!(!mc.stages.contains_key(&vec![Boundary::End]));
+        assert!(mc
+            .stages
+            .values()
+            .all(|stats| !stats.1.contains_key(&Boundary::Start)));
+    }
+
+    #[test]
+    fn boundary_order() {
+        // The end of the term must sort first, like `None` did, to keep generation unchanged
+        assert!(Boundary::End < Boundary::Tok('\0'));
+        assert!(Boundary::<char>::Start < Boundary::End);
+    }
+
+    #[test]
+    fn train_with_boundaries1() {
+        let mut mc = MarkovChain::new(2);
+        mc.train("ab".chars());
+        let mut bounded = MarkovChain::new(2);
+        bounded.train_with_boundaries(
+            vec![
+                Boundary::Start,
+                Boundary::Tok('a'),
+                Boundary::Tok('b'),
+                Boundary::End,
+            ]
+            .into_iter(),
+        );
+        assert_eq!(mc.stages, bounded.stages);
+        assert_eq!(mc.alphabet, bounded.alphabet);
+
+        // No start or end, so only the transition between them is learnt
+        let mut mc = MarkovChain::new(2);
+        mc.train_with_boundaries("ab".chars().map(Boundary::Tok));
+        assert_eq!(mc.stages.len(), 1);
+        assert!(has_key(&mc, "a"));
+        assert!(!has_key_w_none_predict(&mc, "b"));
+        assert_eq!(mc.alphabet.0, 2);
+    }
+
+    #[test]
+    fn generate_size1_fast_path() {
+        for start_mode in [StartMode::Incremental, StartMode::Openings] {
+            let chain = || {
+                let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+                for word in ["rust", "trust", "crust", "bust", "dust"] {
+                    mc.train(word.chars());
+                }
+                // A stage which was never followed by anything falls back to the alphabet
+                mc.train_with_boundaries([Boundary::Start, Boundary::Tok('q')].into_iter());
+                mc.set_start_mode(start_mode);
+                mc
+            };
+            let mut mc = chain();
+            let mut general = chain();
+            for _ in 0..200 {
+                assert_eq!(mc.generate_max_look(1), general.generate_general_path(1));
+            }
+        }
+    }
+
+    #[cfg(feature = "deterministic")]
+    #[test]
+    fn deterministic_stages() {
+        let mut mc = MarkovChain::without_rng(3);
+        for word in ["rust", "trust", "crust", "bust"] {
+            mc.train(word.chars());
+        }
+        let stages: Vec<_> = mc.stages.keys().collect();
+        let mut sorted = stages.clone();
+        sorted.sort();
+        assert_eq!(stages, sorted);
+    }
+
+    #[test]
+    fn train_counted1() {
+        let words = ["aardvark", "abacus", "abbey", "", "zebra"];
+        let mut mc = MarkovChain::without_rng(3);
+        mc.train_counted(words.iter().map(|word| (word.chars().collect(), 1)));
+        let mut reference = MarkovChain::without_rng(3);
+        for word in words {
+            reference.train(word.chars());
+        }
+        assert_eq!(mc.stages, reference.stages);
+        assert_eq!(mc.alphabet, reference.alphabet);
+        assert_eq!(mc.lengths, reference.lengths);
+
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train_counted(vec![(vec!['a', 'b'], 3), (vec!['c'], 0)]);
+        let mut reference = MarkovChain::without_rng(1);
+        for _ in 0..3 {
+            reference.train("ab".chars());
+        }
+        assert_eq!(mc.stages, reference.stages);
+        assert_eq!(mc.alphabet, reference.alphabet);
+        assert_eq!(mc.lengths, reference.lengths);
+    }
+
+    #[test]
+    fn train_with_aliases1() {
+        let digit = |c: &char| if c.is_ascii_digit() { '#' } else { *c };
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["a12", "a13", "b1"] {
+            mc.train_with_aliases(word.chars(), &digit);
+        }
+        assert!(mc.has_context(&['a', '#']));
+        assert!(!mc.has_context(&['1']));
+        assert_eq!(
+            mc.members[&'#'],
+            BTreeMap::from([('1', 3), ('2', 1), ('3', 1)])
+        );
+
+        for _ in 0..20 {
+            let term = mc.generate_unaliased(2);
+            assert!(!term.contains(&'#'));
+            assert!(mc.can_generate(&term.iter().map(digit).collect::<Vec<_>>()));
+        }
+        let ones = (0..500).filter(|_| mc.unalias(&['#']) == ['1']).count();
+        assert!(ones > 250 && ones < 350);
+        assert_eq!(mc.unalias(&['a', 'x']), ['a', 'x']);
+
+        let mut other = MarkovChain::new_with_rng(2, easy_rng());
+        other.train_with_aliases("4".chars(), &digit);
+        mc.merge_weighted(&other, 1., 2.);
+        assert_eq!(mc.members[&'#'][&'4'], 2);
+    }
+
+    #[test]
+    fn train_stream1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train_stream("abcabca".chars());
+        assert_eq!(mc.stages.len(), 3);
+        assert!(!mc.stages.contains_key(&vec![Boundary::Start]));
+        assert!(mc.stages.values().all(|stats| stats.1.len() == 1));
+        assert_eq!(mc.alphabet.0, 7);
+
+        let text: String = mc.token_stream(1).take(30).collect();
+        assert_eq!(text.len(), 30);
+        assert!(text.contains("abca"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn train_with_boundaries_misplaced_start() {
+        let mut mc = MarkovChain::new(2);
+        mc.train_with_boundaries(vec![Boundary::Tok('a'), Boundary::Start].into_iter());
+    }
+
+    #[cfg(not(feature = "deterministic"))]
+    #[test]
+    fn reserve1() {
+        let mut mc = MarkovChain::new(2);
+        mc.reserve(1000);
+        assert!(mc.stages.capacity() >= 1000);
+        mc.train("abc".chars());
+        assert!(mc.stages.capacity() >= 1000);
+    }
+
+    #[test]
+    fn branching1() {
+        let mut mc = MarkovChain::new(2);
+        assert_eq!(mc.average_branching(), 0.);
+
+        mc.train("abc".chars());
+        assert_eq!(mc.average_branching(), 1.);
+
+        mc.train("abd".chars());
+        assert_eq!(mc.branching_at_order(1), 6. / 5.);
+        assert_eq!(mc.branching_at_order(2), 5. / 4.);
+        assert_eq!(mc.branching_at_order(3), 0.);
+        assert_eq!(mc.average_branching(), 11. / 9.);
+    }
+
+    #[test]
+    fn extract_order1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        mc.train("abd".chars());
+
+        let order1 = mc.extract_order(1);
+        assert_eq!(order1.size, 1);
+        assert_eq!(order1.stages.len(), 5);
+        assert!(order1.stages.keys().all(|stage| stage.len() == 1));
+        assert_eq!(order1.stages[&stage("a")], mc.stages[&stage("a")]);
+        assert_eq!(order1.alphabet, mc.alphabet);
+        assert_eq!(order1.branching_at_order(1), mc.branching_at_order(1));
+
+        let order2 = mc.extract_order(2);
+        assert_eq!(order2.stages.len(), 4);
+        assert!(!has_key(&order2, "a"));
+        assert!(has_key(&order2, "ab"));
+    }
+
+    #[test]
+    fn subset_to_tokens1() {
+        let mut mc = MarkovChain::new(2);
+        for word in ["café", "naïve", "cab", "ñandu", "bead"] {
+            mc.train(word.chars());
+        }
+        let keep: BTreeSet<char> = ('a'..='z').collect();
+        let mut subset = mc.subset_to_tokens(&keep);
+        assert!(subset.has_context(&['c', 'a']));
+        assert!(subset.has_context(&['v', 'e']));
+        assert!(!subset.has_context(&['f', 'é']));
+        assert!(!subset.has_context(&['ï']));
+        // 'f' was only ever followed by 'é'
+        assert!(!subset.has_context(&['f']));
+        // 'ï' was counted after 'a' as a stage of length 1 and of length 2
+        assert_eq!(subset.stages[&stage("a")].0, mc.stages[&stage("a")].0 - 2);
+        assert_eq!(subset.alphabet.0, mc.alphabet.0 - 3);
+        for _ in 0..100 {
+            assert!(subset.generate().iter().all(|c| keep.contains(c)));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn extract_order_too_long() {
+        MarkovChain::<char, ()>::without_rng(2).extract_order(3);
+    }
+
+    #[test]
+    fn expected_length1() {
+        let mut mc = MarkovChain::without_rng(2);
+        assert_eq!(mc.expected_length(2), 0.);
+
+        mc.train("ab".chars());
+        mc.train("".chars());
+        assert_eq!(mc.expected_length(2), 1.);
+
+        // Ends after each `a` with probability 2/3
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train("a".chars());
+        mc.train("aa".chars());
+        assert!((mc.expected_length(1) - 1.5).abs() < 1e-6);
+
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train_stream("ab".chars());
+        assert_eq!(mc.expected_length(1), 1000.);
+    }
+
+    #[test]
+    fn effective_vocab_size1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        mc.train("abd".chars());
+        assert_eq!(mc.effective_vocab_size(2, 100), 4);
+        assert_eq!(mc.effective_vocab_size(2, 0), 0);
+
+        // `x` is in the alphabet, but nothing can lead to it
+        mc.train_with_boundaries([Boundary::Tok('x'), Boundary::End].into_iter());
+        assert_eq!(mc.alphabet.1.len(), 5);
+        assert_eq!(mc.effective_vocab_size(2, 100), 4);
+    }
+
+    #[test]
+    fn most_surprising1() {
+        let mut mc = MarkovChain::without_rng(1);
+        for word in ["ab", "ab", "ab", "ac"] {
+            mc.train(word.chars());
+        }
+        let (pos, surprisal) = mc.most_surprising(&['a', 'c']).unwrap();
+        assert_eq!(pos, 1);
+        assert!((surprisal - 4_f64.ln()).abs() < 1e-9);
+
+        assert_eq!(mc.most_surprising(&['a', 'b']), Some((1, -(0.75_f64.ln()))));
+        assert_eq!(mc.most_surprising(&['b', 'a']).unwrap().1, f64::INFINITY);
+        // 'b' was never followed by 'a'
+        assert_eq!(
+            mc.most_surprising(&['a', 'b', 'a', 'b']),
+            Some((2, f64::INFINITY))
+        );
+        assert_eq!(mc.most_surprising(&[]), None);
+    }
+
+    #[test]
+    fn score1() {
+        let mut mc = MarkovChain::without_rng(2);
+        for word in ["ab", "ab", "ac"] {
+            mc.train(word.chars());
+        }
+        // "a" always starts, "b" is 2 out of 3 after it, and "b" always ends
+        assert!((mc.score("ab".chars()) - (2_f64 / 3.).ln()).abs() < 1e-9);
+        assert!(mc.score("ab".chars()) > mc.score("ac".chars()));
+        assert_eq!(mc.score("b".chars()), f64::NEG_INFINITY);
+        assert_eq!(mc.score("a".chars()), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn attribution1() {
+        let mut mc = MarkovChain::without_rng(3);
+        mc.train("abc".chars());
+        assert_eq!(
+            mc.attribution(&['a', 'b', 'c']),
+            vec![(1, 0.25), (2, 0.25), (3, 0.5)]
+        );
+        assert_eq!(mc.attribution(&[]), vec![(1, 1.)]);
+
+        // Nothing was seen after 'x', so 'a' comes from the alphabet
+        let attribution = mc.attribution(&['x', 'a']);
+        assert_eq!(attribution.len(), 2);
+        assert_eq!(attribution[0].0, 0);
+        assert!((attribution[0].1 - 1. / 3.).abs() < 1e-9);
+        assert_eq!(attribution[1].0, 1);
+    }
+
+    #[test]
+    fn coverage1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        mc.train("bcd".chars());
+
+        let vocab: Vec<Vec<char>> = ["abc", "bcd", "abcd", "dcb"]
+            .iter()
+            .map(|w| w.chars().collect())
+            .collect();
+        assert_eq!(mc.coverage(&vocab), 0.75);
+        assert_eq!(mc.coverage(&vocab[..2]), 1.);
+        assert!(mc.coverage(&[]).is_nan());
+    }
+
+    #[test]
+    fn min_sufficient_order1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("ab".chars());
+        mc.train("bc".chars());
+        assert_eq!(mc.min_sufficient_order(&['a', 'b']), Some(1));
+        assert_eq!(mc.min_sufficient_order(&['a', 'b', 'c']), Some(1));
+        assert_eq!(mc.min_sufficient_order(&['c']), None);
+
+        // Without the stage after `b`, only a lookbehind of 2 knows `a`, `b` can end
+        let order2 = mc.extract_order(2);
+        assert_eq!(order2.min_sufficient_order(&['a', 'b']), Some(2));
+        assert_eq!(order2.min_sufficient_order(&['a', 'b', 'c']), None);
+    }
+
+    #[test]
+    fn alphabet1() {
+        let mut mc = MarkovChain::without_rng(2);
+        assert_eq!(mc.alphabet_size(), 0);
+        assert_eq!(mc.alphabet().next(), None);
+
+        mc.train("banana".chars());
+        assert_eq!(mc.alphabet_size(), 3);
+        assert_eq!(
+            mc.alphabet().collect::<Vec<_>>(),
+            vec![(&'a', 3), (&'b', 1), (&'n', 2)]
+        );
+        assert!(mc.alphabet().all(|(t, _)| t.is_lowercase()));
+    }
+
+    #[test]
+    fn export_alphabet1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abb".chars());
+        let alphabet = mc.export_alphabet();
+        assert_eq!(alphabet, BTreeMap::from([('a', 1), ('b', 2)]));
+
+        let mut other = MarkovChain::without_rng(3);
+        other.import_alphabet_into(alphabet.clone());
+        assert_eq!(other.alphabet, mc.alphabet);
+        assert!(other.stages.is_empty());
+
+        other.import_alphabet_into(BTreeMap::from([('b', 1), ('c', 0)]));
+        assert_eq!(other.alphabet, (4, BTreeMap::from([('a', 1), ('b', 3)])));
+    }
+
+    #[test]
+    fn merge1() {
+        let mut both = MarkovChain::without_rng(3);
+        both.train("abc".chars());
+        both.train("def".chars());
+        both.train("abd".chars());
+
+        let mut merged = MarkovChain::new(3);
+        merged.train("abc".chars());
+        let mut shard = MarkovChain::without_rng(3);
+        shard.train("def".chars());
+        shard.train("abd".chars());
+        merged.merge(&shard);
+        assert_eq!(merged.stages, both.stages);
+        assert_eq!(merged.alphabet, both.alphabet);
+        assert_eq!(merged.lengths, both.lengths);
+
+        assert!(merged.try_merge(&MarkovChain::without_rng(2)).is_err());
+        assert_eq!(merged.stages, both.stages);
+    }
+
+    #[test]
+    fn merge_weighted1() {
+        let mut mc = MarkovChain::new(2);
+        mc.train("abc".chars());
+        let mut other = MarkovChain::new(2);
+        other.train("abd".chars());
+        let mut both = MarkovChain::new(2);
+        both.train("abc".chars());
+        both.train("abd".chars());
+
+        let mut merged = MarkovChain::new(2);
+        merged.train("abc".chars());
+        merged.merge_weighted(&other, 1., 1.);
+        assert_eq!(merged.stages, both.stages);
+        assert_eq!(merged.alphabet, both.alphabet);
+
+        // Only the first chain is kept, with its counts doubled
+        mc.merge_weighted(&other, 2., 0.);
+        assert!(has_key(&mc, "bc"));
+        assert!(!has_key(&mc, "bd"));
+        assert_eq!(mc.alphabet.0, 6);
+        assert_eq!(
+            mc.stages[&stage("ab")],
+            (2, BTreeMap::from([(Boundary::Tok('c'), 2)]))
+        );
+
+        // Rounding to 0 removes it, and the totals still add up
+        both.set_rounding_mode(RoundingMode::Round);
+        both.merge_weighted(&other, 1., 0.4);
+        assert_eq!(both.stages[&stage("ab")].0, 2);
+        assert!(both
+            .stages
+            .values()
+            .all(|stats| stats.0 == stats.1.values().sum::<usize>()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_weighted_different_sizes() {
+        let mut mc: MarkovChain<char, _> = MarkovChain::new(2);
+        mc.merge_weighted(&MarkovChain::new(3), 1., 1.);
+    }
+
+    #[test]
+    fn kl_divergence1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        mc.train("abd".chars());
+        assert_eq!(mc.kl_divergence(&mc), 0.);
+
+        let mut other = MarkovChain::without_rng(2);
+        other.train("abc".chars());
+        let divergence = mc.kl_divergence(&other);
+        assert!(divergence > 0. && divergence.is_finite());
+
+        other.train("abc".chars());
+        assert!(mc.kl_divergence(&other) > divergence);
+
+        let mut unrelated = MarkovChain::without_rng(2);
+        unrelated.train("xyz".chars());
+        assert!(mc.kl_divergence(&unrelated) > 0.);
+        assert!(MarkovChain::without_rng(2).kl_divergence(&mc).is_nan());
+    }
+
+    #[test]
+    fn can_generate1() {
+        let mut mc = MarkovChain::new(2);
+        mc.train("abc".chars());
+        mc.train("bbc".chars());
+
+        assert!(mc.can_generate(&['a', 'b', 'c']));
+        assert!(mc.can_generate(&['b', 'b', 'c']));
+        assert!(!mc.can_generate(&['a', 'b', 'b', 'c']));
+        assert!(!mc.can_generate(&['a', 'b']));
+        assert!(!mc.can_generate(&['x']));
+        assert!(!mc.can_generate(&[]));
+
+        mc.train("".chars());
+        assert!(mc.can_generate(&[]));
+    }
+
+    #[test]
+    fn transitions_fall_back() {
+        let mut mc = MarkovChain::new(2);
+        mc.train_with_boundaries("ab".chars().map(Boundary::Tok));
+
+        // No start stage, so the first item comes from the alphabet
+        let transitions: Vec<Transition> = mc.transitions(&['a', 'b'], 2).collect();
+        assert_eq!(
+            transitions,
+            vec![
+                Transition {
+                    order: 0,
+                    count: 1,
+                    total: 2
+                },
+                Transition {
+                    order: 1,
+                    count: 1,
+                    total: 1
+                },
+                Transition {
+                    order: 0,
+                    count: 0,
+                    total: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn without_rng1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        mc.train("abd".chars());
+        assert!(mc.can_generate(&['a', 'b', 'c']));
+        assert!(!mc.is_degenerate());
+
+        let mut rng = easy_rng();
+        assert!(mc.can_generate(&mc.generate_with_rng(&mut rng, 2)));
+
+        let mut mc = mc.with_rng(easy_rng());
+        let word = mc.generate();
+        assert!(mc.can_generate(&word));
+    }
+
+    #[test]
+    fn is_degenerate1() {
+        let mut mc = MarkovChain::new(3);
+        assert!(mc.is_degenerate());
+
+        mc.train("".chars());
+        assert!(mc.is_degenerate());
+
+        let mut mc = MarkovChain::new(3);
+        mc.train("abc".chars());
+        assert!(mc.is_degenerate());
+
+        mc.train("abd".chars());
+        assert!(!mc.is_degenerate());
+
+        let mut mc = MarkovChain::new(3);
+        mc.train("a".chars());
+        mc.train("aaaa".chars());
+        assert!(mc.is_degenerate());
+    }
+
+    #[test]
+    fn tuples1() {
+        // (pitch, duration). The default, (0, 0), is a rest
+        let tunes: Vec<Vec<(u8, u8)>> = vec![
+            vec![(60, 1), (62, 1), (0, 0), (64, 2)],
+            vec![(60, 1), (0, 0), (67, 4)],
+            vec![(0, 0), (60, 2)],
+        ];
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for tune in tunes.iter() {
+            mc.train(tune.iter().cloned());
+        }
+        assert_eq!(mc.alphabet.1.len(), 6);
+        assert_eq!(mc.alphabet.1[&(0, 0)], 3);
+
+        for _ in 0..20 {
+            let tune = mc.generate();
+            assert!(mc.can_generate(&tune));
+            assert!(tune.iter().all(|note| mc.alphabet.1.contains_key(note)));
+        }
+        assert!(mc.can_generate(&[(0, 0), (60, 2)]));
+        assert_eq!(
+            mc.generate_greedy(2, 10, TieBreak::Last),
+            vec![(60, 1), (62, 1), (0, 0), (64, 2)]
+        );
+    }
+
+    fn easy_rng() -> impl Rng {
+        rand::prng::XorShiftRng::from_seed([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])
+    }
+
+    #[test]
+    fn weighted_choice1() {
         let mut rng = easy_rng();
         let mut data = BTreeMap::new();
         data.insert(Some('a'), 1);
         data.insert(Some('b'), 2);
-        let data = (data.values().sum(), data);
         let mut stats: HashMap<Option<char>, usize> = HashMap::new();
 
-        for _ in 0..1000 {
-            let choice = weighted_choice(&mut rng, &data);
-            *stats.entry(choice).or_default() += 1;
+        for _ in 0..1000 {
+            let choice = weighted_choice(&mut rng, &data).unwrap();
+            *stats.entry(choice).or_default() += 1;
+        }
+        assert_eq!(stats[&Some('a')], 328);
+        assert_eq!(stats[&Some('b')], 672);
+    }
+
+    #[test]
+    fn weighted_choice2() {
+        let mut rng = easy_rng();
+        let mut data = BTreeMap::new();
+        data.insert(Some('a'), 1);
+        data.insert(Some('b'), 200);
+        data.insert(Some('c'), 200);
+        data.insert(Some('d'), 200);
+        let mut stats: HashMap<Option<char>, usize> = HashMap::new();
+
+        for _ in 0..10_000 {
+            let choice = weighted_choice(&mut rng, &data).unwrap();
+            *stats.entry(choice).or_default() += 1;
+        }
+        assert_eq!(stats[&Some('a')], 15);
+        assert_eq!(stats[&Some('b')], 3293);
+        assert_eq!(stats[&Some('c')], 3405);
+        assert_eq!(stats[&Some('d')], 3287);
+    }
+
+    #[test]
+    fn validate1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        assert_eq!(mc.validate(), Ok(()));
+
+        let mut wrong_total = mc.clone_stages();
+        wrong_total.stages.get_mut(&stage("a")).unwrap().0 += 1;
+        assert!(wrong_total.validate().is_err());
+
+        let mut empty_stage = mc.clone_stages();
+        empty_stage.stages.insert(stage("x"), (0, BTreeMap::new()));
+        assert!(empty_stage.validate().is_err());
+
+        let mut misplaced = mc.clone_stages();
+        let counts = misplaced.stages[&stage("a")].clone();
+        misplaced
+            .stages
+            .insert(vec![Boundary::Tok('a'), Boundary::Start], counts);
+        assert!(misplaced.validate().is_err());
+
+        let mut wrong_alphabet = mc.clone_stages();
+        wrong_alphabet.alphabet.1.insert('z', 0);
+        assert!(wrong_alphabet.validate().is_err());
+    }
+
+    impl<T: Hash + Eq + Clone + Default + Ord + Debug, R> MarkovChain<T, R> {
+        /// A copy of this chain without its random number generator.
+        fn clone_stages(&self) -> MarkovChain<T, ()> {
+            MarkovChain {
+                size: self.size,
+                rng: (),
+                stages: self.stages.clone(),
+                alphabet: self.alphabet.clone(),
+                lengths: self.lengths.clone(),
+                members: self.members.clone(),
+                start_mode: self.start_mode,
+                rounding: self.rounding,
+                cumulative: StageMap::new(),
+                openings: BTreeMap::new(),
+            }
+        }
+    }
+
+    /// Random terms of up to 5 items out of 4, including empty ones.
+    fn random_terms(rng: &mut impl Rng, num: usize) -> Vec<Vec<u8>> {
+        (0..num)
+            .map(|_| {
+                let len = rng.gen_range(0, 6);
+                (0..len).map(|_| rng.gen_range(0, 4)).collect()
+            })
+            .collect()
+    }
+
+    /// Runs everything which generates from `mc` with `look`, which must end by itself unless
+    /// `all` is false, and checks it's still valid.
+    fn generate_everything<R: Rng>(mc: &mut MarkovChain<u8, R>, look: usize, all: bool) {
+        mc.validate().unwrap();
+        mc.most_probable_term(look, 8);
+        mc.count_possible_terms(look, 8);
+        mc.expected_length(look);
+        mc.generate_annealed(look, 2., 0.5, 3, 8);
+        mc.generate_positional_temp(look, &[0.5, 2.], 8);
+        mc.generate_greedy(look, 8, TieBreak::Random);
+        mc.generate_confident(look, 2, 8);
+        mc.generate_constrained(look, |term| term.len() > 1, 8, 20);
+        mc.token_stream(look).take(20).count();
+        mc.most_likely_next(&[0, 1], TieBreak::Random);
+        mc.sample_next(&[2]);
+        mc.sample_token();
+        mc.attribution(&[0, 3]);
+        mc.topk_mass(&[1], 2);
+        mc.predict_next(&[3, 3]);
+        mc.min_sufficient_order(&[0, 1, 2]);
+        mc.dead_end_contexts();
+        mc.reachable_contexts();
+        mc.stats();
+        let _ = mc.transition_matrix();
+        if !all {
+            return;
+        }
+
+        mc.generate_max_look(look);
+        mc.length_histogram(5, look);
+        mc.generate_typical_length(look, 50.);
+        mc.generate_exact_len(look, 2, 5);
+        mc.generate_with_callback(look, |_, _| ControlFlow::Continue(()));
+        mc.generate_matching(look, &[TokenClass::Any, TokenClass::Exact(1)], 5);
+        mc.complete(&[0], look, 2, 5);
+        mc.generate_smooth(look);
+        mc.distinct_terms(look, 3, 5);
+        mc.generate_diverse(look, 3, 2, 5);
+        let (term, draws) = mc.generate_recorded(look);
+        assert_eq!(mc.replay(&draws, look), term);
+        mc.generate_order_window(1, look);
+        mc.generate_with_weight_fn(look, |t, pos| (*t as usize + pos) as f64);
+        mc.generate_authentic_start(look);
+        mc.generate_rank_limited(look, 2);
+        mc.generate_until(look, |term| !term.is_empty(), 5);
+        mc.generate_min_prob(look, 0.2, 5);
+        mc.sample_training_like(2, look);
+        mc.nth(3, look);
+        mc.generate_from_key("key", look);
+        mc.effective_vocab_size(look, 5);
+        mc.session(look).next_term();
+        mc.validate().unwrap();
+    }
+
+    #[test]
+    fn generating_never_panics() {
+        let mut rng = easy_rng();
+        for round in 0..200 {
+            let size = rng.gen_range(1, 5);
+            let seed = [round as u8 + 1; 16];
+            let mut mc = MarkovChain::new_with_rng(size, rand::prng::XorShiftRng::from_seed(seed));
+            let num_terms = rng.gen_range(1, 6);
+            let terms = random_terms(&mut rng, num_terms);
+            match round % 3 {
+                0 => terms
+                    .into_iter()
+                    .for_each(|term| mc.train(term.into_iter())),
+                1 => mc.train_counted(terms.into_iter().map(|term| (term, 2))),
+                _ => {
+                    for term in terms {
+                        let start = rng.gen::<bool>().then_some(Boundary::Start);
+                        let items = term.into_iter().map(Boundary::Tok);
+                        let term = start.into_iter().chain(items).chain(Some(Boundary::End));
+                        mc.train_with_boundaries(term);
+                    }
+                }
+            }
+            for look in 1..=size {
+                generate_everything(&mut mc, look, true);
+            }
+
+            // These keep every count which isn't 0, so generating still ends
+            let mut other =
+                MarkovChain::new_with_rng(size, rand::prng::XorShiftRng::from_seed(seed));
+            random_terms(&mut rng, 3)
+                .into_iter()
+                .for_each(|term| other.train(term.into_iter()));
+            match rng.gen_range(0, 5) {
+                0 => mc.merge_weighted_with_rounding(
+                    &other,
+                    rng.gen_range(0.01, 2.),
+                    rng.gen_range(0.01, 2.),
+                    RoundingMode::Ceil,
+                ),
+                1 => mc.merge_weighted(&other, rng.gen_range(0.5, 2.), rng.gen_range(0.5, 2.)),
+                2 => {
+                    let term = random_terms(&mut rng, 1).remove(0);
+                    let weight = rng.gen_range(0., 1.);
+                    mc.update_with_rounding(term.into_iter(), weight, RoundingMode::Ceil);
+                }
+                3 => mc.quantize(rng.gen_range(1, 5)),
+                _ => {
+                    mc.collapse_equivalent(rng.gen_range(0., 0.3));
+                }
+            }
+            for look in 1..=size {
+                generate_everything(&mut mc, look, true);
+            }
+
+            // After these, generating might go on forever
+            let keep: BTreeSet<u8> = (0..4).filter(|_| rng.gen::<bool>()).collect();
+            let mut subset = mc.subset_to_tokens(&keep);
+            for look in 1..=size {
+                generate_everything(&mut subset, look, false);
+            }
+            let order = rng.gen_range(1, size + 1);
+            let mut extracted = mc.extract_order(order);
+            generate_everything(&mut extracted, order, false);
+        }
+    }
+
+    #[test]
+    fn weighted_choice_nothing() {
+        let mut rng = easy_rng();
+        let mut data: BTreeMap<char, usize> = BTreeMap::new();
+        assert_eq!(weighted_choice(&mut rng, &data), None);
+        data.insert('a', 0);
+        assert_eq!(weighted_choice(&mut rng, &data), None);
+        data.insert('b', 1);
+        for _ in 0..100 {
+            assert_eq!(weighted_choice(&mut rng, &data), Some('b'));
+        }
+    }
+
+    fn prediction_result<R: Rng>(mc: &mut MarkovChain<char, R>) -> String {
+        mc.generate()
+            .into_iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+            .join("")
+    }
+
+    fn prediction_result_size<R: Rng>(mc: &mut MarkovChain<char, R>, size: usize) -> String {
+        mc.generate_max_look(size)
+            .into_iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+            .join("")
+    }
+
+    #[test]
+    fn generate_string1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train("abc".chars());
+        mc.train("bbc".chars());
+        mc.train("acb".chars());
+
+        let mut other = MarkovChain::new_with_rng(2, easy_rng());
+        other.train("abc".chars());
+        other.train("bbc".chars());
+        other.train("acb".chars());
+
+        for size in [2, 1, 2] {
+            assert_eq!(
+                mc.generate_string(size),
+                prediction_result_size(&mut other, size)
+            );
+        }
+    }
+
+    #[test]
+    fn generate_capitalized1() {
+        let mut mc = MarkovChain::new(2);
+        mc.train("ßa".chars());
+        assert_eq!(mc.generate_capitalized(2), "SSa");
+
+        let mut mc = MarkovChain::new(2);
+        mc.train("".chars());
+        assert_eq!(mc.generate_capitalized(2), "");
+        assert!(mc.try_generate_capitalized(3).is_err());
+
+        assert_eq!(capitalize("éa"), "Éa");
+        assert_eq!(capitalize("Ab"), "Ab");
+    }
+
+    #[test]
+    fn generate_collect1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train("abc".chars());
+        mc.train("bbc".chars());
+        mc.train("acb".chars());
+
+        let mut other = MarkovChain::new_with_rng(2, easy_rng());
+        other.train("abc".chars());
+        other.train("bbc".chars());
+        other.train("acb".chars());
+
+        let word: String = mc.generate_collect(2);
+        assert_eq!(word, prediction_result_size(&mut other, 2));
+        let word: BTreeSet<char> = mc.generate_collect(2);
+        assert_eq!(word, other.generate_max_look(2).into_iter().collect());
+    }
+
+    #[test]
+    fn generate_with_rng1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train("abc".chars());
+        mc.train("bbc".chars());
+        mc.train("acb".chars());
+
+        let mut rng = easy_rng();
+        let words: Vec<Vec<char>> = (0..5).map(|_| mc.generate_with_rng(&mut rng, 2)).collect();
+        let expected: Vec<Vec<char>> = (0..5).map(|_| mc.generate_max_look(2)).collect();
+        assert_eq!(words, expected);
+    }
+
+    #[test]
+    fn sample_token1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        assert_eq!(mc.sample_token(), None);
+
+        mc.train("abbb".chars());
+        let mut stats: HashMap<char, usize> = HashMap::new();
+        for _ in 0..1000 {
+            *stats.entry(mc.sample_token().unwrap()).or_default() += 1;
+        }
+        assert_eq!(stats.len(), 2);
+        assert!(stats[&'a'] > 200 && stats[&'a'] < 300, "{:?}", stats);
+    }
+
+    #[test]
+    fn token_stream1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train("abc".chars());
+        mc.train("bbc".chars());
+        mc.train("acb".chars());
+
+        let mut other = MarkovChain::new_with_rng(2, easy_rng());
+        other.train("abc".chars());
+        other.train("bbc".chars());
+        other.train("acb".chars());
+
+        for _ in 0..5 {
+            let mut stream = mc.token_stream(2);
+            let word: Vec<char> = stream.by_ref().collect();
+            assert_eq!(stream.next(), None);
+            assert_eq!(word, other.generate_max_look(2));
+        }
+
+        // Only the tokens that are asked for are generated
+        let first: Vec<char> = mc.token_stream(1).take(1).collect();
+        assert_eq!(first.len(), 1);
+    }
+
+    #[test]
+    fn generate_iter1() {
+        let train = || {
+            let mut mc = MarkovChain::from_seed(3, 4);
+            for word in ["rust", "trust", "crust", "bust", "dust"] {
+                mc.train(word.chars());
+            }
+            mc
+        };
+        let (mut mc, mut other) = (train(), train());
+        for start_mode in [StartMode::Incremental, StartMode::Openings] {
+            mc.set_start_mode(start_mode);
+            other.set_start_mode(start_mode);
+            for _ in 0..10 {
+                let mut iter = mc.generate_iter(3);
+                let word: Vec<char> = iter.by_ref().collect();
+                assert_eq!(iter.next(), None);
+                assert_eq!(word, other.generate_max_look(3));
+            }
+        }
+
+        // The opening is chosen straight away, but nothing else is generated until it's needed
+        let first: Vec<char> = mc.generate_iter(3).take(1).collect();
+        assert_eq!(first.len(), 1);
+        assert!(MarkovChain::<char, _>::new(2).try_generate_iter(2).is_err());
+    }
+
+    #[test]
+    fn generate_greedy1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train("abc".chars());
+        mc.train("abd".chars());
+
+        assert_eq!(
+            mc.generate_greedy(2, 10, TieBreak::First),
+            vec!['a', 'b', 'c']
+        );
+        assert_eq!(
+            mc.generate_greedy(2, 10, TieBreak::Last),
+            vec!['a', 'b', 'd']
+        );
+        assert_eq!(mc.generate_greedy(2, 2, TieBreak::Last), vec!['a', 'b']);
+
+        let words: BTreeSet<Vec<char>> = (0..20)
+            .map(|_| mc.generate_greedy(2, 10, TieBreak::Random))
+            .collect();
+        assert_eq!(words.len(), 2);
+
+        mc.train("abc".chars());
+        assert_eq!(
+            mc.generate_greedy(2, 10, TieBreak::Last),
+            vec!['a', 'b', 'c']
+        );
+
+        // Always choosing 'a' never reaches the end
+        let mut mc = MarkovChain::new(1);
+        mc.train("aaa".chars());
+        assert_eq!(mc.generate_greedy(1, 5, TieBreak::First), vec!['a'; 5]);
+    }
+
+    #[test]
+    fn generate_exact_len1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["ab", "abcd", "abcdef", "xyz"] {
+            mc.train(word.chars());
+        }
+
+        for _ in 0..10 {
+            assert_eq!(
+                mc.generate_exact_len(2, 4, 100),
+                Some(vec!['a', 'b', 'c', 'd'])
+            );
+            assert_eq!(mc.generate_exact_len(2, 3, 100), Some(vec!['x', 'y', 'z']));
+        }
+        assert_eq!(mc.generate_exact_len(2, 5, 100), None);
+        assert_eq!(mc.generate_exact_len(2, 0, 100), None);
+        assert_eq!(mc.generate_exact_len(2, 4, 0), None);
+    }
+
+    #[test]
+    fn generate_with_length_bounds1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["ab", "abcd", "abcdef", "xyz"] {
+            mc.train(word.chars());
+        }
+
+        for _ in 0..10 {
+            // "abcdef" is cut off, and "ab" is too short
+            let word: String = mc
+                .generate_with_length_bounds(2, 3, 4, 100)
+                .unwrap()
+                .into_iter()
+                .collect();
+            assert!(word == "abcd" || word == "xyz", "{}", word);
+            assert!(mc.generate_with_length_bounds(2, 0, 2, 1).unwrap().len() <= 2);
+        }
+        assert_eq!(mc.generate_with_length_bounds(2, 7, 10, 100), None);
+        assert_eq!(mc.generate_with_length_bounds(2, 1, 4, 0), None);
+        assert!(mc.try_generate_with_length_bounds(2, 4, 3, 100).is_err());
+        assert!(mc.try_generate_with_length_bounds(3, 1, 3, 100).is_err());
+    }
+
+    #[test]
+    fn generate_until1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["abc", "abd", "xyz"] {
+            mc.train(word.chars());
+        }
+
+        for _ in 0..10 {
+            let word = mc.generate_until(2, |term| term.ends_with(&['d']), 100);
+            assert_eq!(word, Some(vec!['a', 'b', 'd']));
+        }
+        assert_eq!(mc.generate_until(2, |term| term.len() > 3, 100), None);
+        assert_eq!(mc.generate_until(2, |_| true, 0), None);
+    }
+
+    #[test]
+    fn generate_min_prob1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for _ in 0..9 {
+            mc.train("abc".chars());
+        }
+        mc.train("abd".chars());
+
+        for _ in 0..10 {
+            assert_eq!(mc.generate_min_prob(2, 0.5, 100), Some(vec!['a', 'b', 'c']));
+        }
+        let words: BTreeSet<Vec<char>> = (0..100)
+            .filter_map(|_| mc.generate_min_prob(2, 0., 1))
+            .collect();
+        assert_eq!(words.len(), 2);
+        assert_eq!(mc.generate_min_prob(2, 1.1, 100), None);
+    }
+
+    #[test]
+    fn most_probable_term1() {
+        let mut mc = MarkovChain::new(1);
+        for word in ["ax", "ay", "az", "b", "b"] {
+            mc.train(word.chars());
+        }
+
+        // 'a' is the most likely first item, but then each of the next is unlikely
+        assert_eq!(mc.generate_greedy(1, 10, TieBreak::First)[0], 'a');
+        assert_eq!(mc.most_probable_term(1, 10), vec!['b']);
+        assert_eq!(mc.most_probable_term(1, 0), Vec::<char>::new());
+
+        let mut mc = MarkovChain::new(2);
+        for word in ["abcd", "abcd", "abce", "abce", "abce", "x"] {
+            mc.train(word.chars());
+        }
+        assert_eq!(mc.most_probable_term(2, 10), vec!['a', 'b', 'c', 'e']);
+        assert_eq!(mc.most_probable_term(2, 3), vec!['x']);
+        assert_eq!(
+            MarkovChain::<char, ()>::without_rng(2).most_probable_term(2, 3),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn count_possible_terms1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("ab".chars());
+        mc.train("cb".chars());
+        mc.train("b".chars());
+        assert_eq!(mc.count_possible_terms(2, 10), 3);
+        assert_eq!(mc.count_possible_terms(1, 1), 1);
+        assert_eq!(mc.count_possible_terms(1, 0), 0);
+
+        let mut mc = MarkovChain::without_rng(1);
+        mc.train("ab".chars());
+        mc.train("ba".chars());
+        mc.train("aa".chars());
+        // "a", "b", "aa", "ab" and "ba", and "a" can come after "a" forever
+        assert_eq!(mc.count_possible_terms(1, 2), 5);
+        assert_eq!(mc.count_possible_terms(1, 500), u128::MAX);
+        assert!(mc.try_count_possible_terms(2, 5).is_err());
+    }
+
+    #[test]
+    fn most_likely_next1() {
+        let mut mc = MarkovChain::new(2);
+        mc.train("abc".chars());
+        mc.train("abd".chars());
+
+        assert_eq!(
+            mc.most_likely_next(&[], TieBreak::First),
+            Some(NextToken::Tok('a'))
+        );
+        assert_eq!(
+            mc.most_likely_next(&['a', 'b'], TieBreak::First),
+            Some(NextToken::Tok('c'))
+        );
+        assert_eq!(
+            mc.most_likely_next(&['a', 'b'], TieBreak::Last),
+            Some(NextToken::Tok('d'))
+        );
+        assert_eq!(
+            mc.most_likely_next(&['a', 'b', 'c'], TieBreak::First),
+            Some(NextToken::End)
+        );
+        // Falls back to the shorter stage
+        assert_eq!(
+            mc.most_likely_next(&['x', 'a'], TieBreak::First),
+            Some(NextToken::Tok('b'))
+        );
+
+        assert_eq!(
+            MarkovChain::<char, _>::new(2).most_likely_next(&[], TieBreak::First),
+            None
+        );
+    }
+
+    #[test]
+    fn sample_next1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        assert_eq!(mc.sample_next(&[]), None);
+
+        mc.train("abc".chars());
+        assert_eq!(mc.sample_next(&[]), Some(NextToken::Tok('a')));
+        assert_eq!(mc.sample_next(&['a', 'b', 'c']), Some(NextToken::End));
+        mc.train("abd".chars());
+        for _ in 0..10 {
+            let next = mc.sample_next(&['a', 'b']);
+            assert!(next == Some(NextToken::Tok('c')) || next == Some(NextToken::Tok('d')));
+        }
+    }
+
+    #[test]
+    fn probabilities1() {
+        let mut mc = MarkovChain::without_rng(2);
+        assert_eq!(mc.probabilities(&[Boundary::Start]), None);
+
+        mc.train("ab".chars());
+        mc.train("a".chars());
+        mc.train("ba".chars());
+        assert_eq!(
+            mc.probabilities(&[Boundary::Start, Boundary::Tok('a')]),
+            Some(BTreeMap::from([
+                (Boundary::End, 0.5),
+                (Boundary::Tok('b'), 0.5)
+            ]))
+        );
+        let first = mc.probabilities(&[Boundary::Start]).unwrap();
+        assert!((first[&Boundary::Tok('a')] - 2. / 3.).abs() < 1e-9);
+        assert!((first.values().sum::<f64>() - 1.).abs() < 1e-9);
+        assert_eq!(mc.probabilities(&[Boundary::Tok('z')]), None);
+        // No fall back to shorter stages
+        assert_eq!(
+            mc.probabilities(&[Boundary::Tok('b'), Boundary::Tok('b')]),
+            None
+        );
+    }
+
+    #[test]
+    fn predict_next1() {
+        let mut mc = MarkovChain::without_rng(2);
+        assert!(mc.predict_next(&[]).is_empty());
+
+        mc.train("ab".chars());
+        mc.train("a".chars());
+        assert_eq!(
+            mc.predict_next(&['a']),
+            BTreeMap::from([(NextToken::End, 0.5), (NextToken::Tok('b'), 0.5)])
+        );
+        assert_eq!(
+            mc.predict_next(&['a', 'b']),
+            BTreeMap::from([(NextToken::End, 1.)])
+        );
+        // Nothing has come after `b` then `a`, so it falls back to what comes after `a`
+        assert_eq!(mc.predict_next(&['b', 'a']), mc.predict_next(&['a']));
+    }
+
+    #[test]
+    fn generate_with_callback1() {
+        let chain = || {
+            let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+            mc.train("abcd".chars());
+            mc
+        };
+
+        let mut calls = Vec::new();
+        let term = chain().generate_with_callback(2, |window, t| {
+            calls.push((window.iter().collect::<String>(), *t));
+            ControlFlow::Continue(())
+        });
+        assert_eq!(term, chain().generate_max_look(2));
+        assert_eq!(
+            calls,
+            vec![
+                ("".to_string(), 'a'),
+                ("a".to_string(), 'b'),
+                ("ab".to_string(), 'c'),
+                ("bc".to_string(), 'd')
+            ]
+        );
+
+        let term = chain().generate_with_callback(2, |_, t| {
+            if *t == 'b' {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(term, vec!['a', 'b']);
+    }
+
+    #[test]
+    fn generate_rank_limited1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["ab", "ab", "ac", "ac", "ad", "xab", "xb"] {
+            mc.train(word.chars());
+        }
+
+        // `b` and `c` are just as likely after `a`, and `d` never comes. Only `b` came after `xa`,
+        // so that falls back to `a`. There's only ever the end after `b` and `c`, so each falls
+        // back until the term ends.
+        let mut seen = BTreeSet::new();
+        for _ in 0..200 {
+            seen.insert(
+                mc.generate_rank_limited(2, 2)
+                    .into_iter()
+                    .collect::<String>(),
+            );
+        }
+        let expected = ["ab", "ac", "xab", "xac", "xb"].map(String::from);
+        assert_eq!(seen, BTreeSet::from(expected));
+
+        assert_eq!(mc.generate_rank_limited(2, 1), vec!['a', 'b']);
+        // Only `a` and `x` come first
+        assert_eq!(mc.generate_rank_limited(2, 3), Vec::<char>::new());
+
+        // Always `a` after `a`, but it's cut off at the longest training term
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train("aaa".chars());
+        assert_eq!(mc.generate_rank_limited(1, 1), vec!['a'; 3]);
+    }
+
+    #[test]
+    fn generate_confident1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["abc", "abc", "abd", "xbd"] {
+            mc.train(word.chars());
+        }
+
+        for _ in 0..50 {
+            assert_eq!(mc.generate_confident(2, 2, 10), vec!['a', 'b', 'c']);
+        }
+        // Every term starts differently, so the first item comes from the alphabet, where `x`,
+        // `y` and `z` are too rare
+        let mut rare_starts = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["xab", "yab", "zac"] {
+            rare_starts.train(word.chars());
+        }
+        let seen: BTreeSet<String> = (0..50)
+            .map(|_| {
+                rare_starts
+                    .generate_confident(2, 2, 10)
+                    .into_iter()
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            seen,
+            BTreeSet::from(["ab".to_string(), "ac".to_string(), "b".to_string()])
+        );
+
+        assert_eq!(mc.generate_confident(2, 100, 10), vec![]);
+        // Without a threshold, it's the same as generating
+        let term = mc.generate_confident(2, 0, 10);
+        assert!(mc.can_generate(&term));
+
+        // The end is rarer than another `a`
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train("aaaa".chars());
+        assert_eq!(mc.generate_confident(1, 2, 10).len(), 10);
+    }
+
+    #[test]
+    fn sample_training_like1() {
+        let mut mc = MarkovChain::new_with_rng(3, easy_rng());
+        for word in ["rust", "trust", "python"] {
+            mc.train(word.chars());
+        }
+        assert!(mc.is_memorised(&"python".chars().collect::<Vec<_>>(), 3));
+        assert!(!mc.is_memorised(&"rust".chars().collect::<Vec<_>>(), 3));
+        assert!(!mc.is_memorised(&"a".chars().collect::<Vec<_>>(), 3));
+
+        let memorised = mc.sample_training_like(100, 3);
+        assert!(!memorised.is_empty());
+        assert!(memorised
+            .iter()
+            .all(|term| term.iter().collect::<String>() == "python"));
+    }
+
+    #[test]
+    fn common_contexts1() {
+        let mut a = MarkovChain::without_rng(1);
+        a.train("ab".chars());
+        let mut b = MarkovChain::without_rng(1);
+        b.train("ac".chars());
+
+        // [Start], [a] are shared, [b] and [c] aren't
+        assert_eq!(a.common_contexts(&b), 2);
+        assert_eq!(a.unique_contexts(&b), 1);
+        assert_eq!(b.unique_contexts(&a), 1);
+        assert_eq!(a.common_contexts(&a), a.stages.len());
+        assert_eq!(
+            a.unique_contexts(&MarkovChain::without_rng(1)),
+            a.stages.len()
+        );
+    }
+
+    #[test]
+    fn generate_with_weight_fn1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for _ in 0..9 {
+            mc.train("ab".chars());
+        }
+        mc.train("ac".chars());
+
+        // Never choose `b`, so the rare `c` is always chosen
+        for _ in 0..20 {
+            let term = mc.generate_with_weight_fn(1, |t, count| match t {
+                'b' => 0.,
+                _ => count as f64,
+            });
+            assert_eq!(term, vec!['a', 'c']);
+        }
+        // Nothing has a positive weight, so the term ends straight away
+        assert_eq!(
+            mc.generate_with_weight_fn(1, |_, _| -1.),
+            Vec::<char>::new()
+        );
+        assert!(mc.try_generate_with_weight_fn(2, |_, _| 1.).is_err());
+    }
+
+    #[test]
+    fn choose_by_weight1() {
+        let mut rng = easy_rng();
+        assert_eq!(
+            choose_by_weight(&mut rng, &[('a', 0.), ('b', 2.), ('c', f64::NAN)]),
+            Some('b')
+        );
+        assert_eq!(choose_by_weight(&mut rng, &[('a', 0.), ('b', -1.)]), None);
+        assert_eq!(choose_by_weight::<char, _>(&mut rng, &[]), None);
+    }
+
+    #[test]
+    fn transition_matrix1() {
+        let mut mc = MarkovChain::without_rng(1);
+        for word in ["ab", "ab", "abb", "ba"] {
+            mc.train(word.chars());
+        }
+        let (items, matrix) = mc.transition_matrix().unwrap();
+        assert_eq!(items, vec!['a', 'b']);
+        // After `a`: b 3 times, the end once. After `b`: a once, b once, the end 3 times
+        assert_eq!(matrix, vec![vec![0., 0.75, 0.25], vec![0.2, 0.2, 0.6]]);
+
+        assert!(MarkovChain::<char, ()>::without_rng(2)
+            .transition_matrix()
+            .is_err());
+        let (items, matrix) = MarkovChain::<char, ()>::without_rng(1)
+            .transition_matrix()
+            .unwrap();
+        assert!(items.is_empty() && matrix.is_empty());
+    }
+
+    #[test]
+    fn topk_mass1() {
+        let mut mc = MarkovChain::without_rng(1);
+        for word in ["ab", "ab", "ab", "ac", "a"] {
+            mc.train(word.chars());
+        }
+        // After `a`: b 3 times, c once, and the end once
+        assert_eq!(mc.topk_mass(&['a'], 0), Some(0.));
+        assert_eq!(mc.topk_mass(&['a'], 1), Some(0.6));
+        assert_eq!(mc.topk_mass(&['a'], 2), Some(0.8));
+        assert_eq!(mc.topk_mass(&['a'], 3), Some(1.));
+        assert_eq!(mc.topk_mass(&['a'], 10), Some(1.));
+        assert_eq!(mc.topk_mass(&[], 1), Some(1.));
+        assert_eq!(
+            MarkovChain::<char, ()>::without_rng(1).topk_mass(&[], 1),
+            None
+        );
+    }
+
+    #[test]
+    fn generate_typical_length1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for word in ["a", "aa", "aaa", "aaaa", "aaaaaaaaaa"] {
+            mc.train(word.chars());
+        }
+        mc.train_with_boundaries(vec![Boundary::Start, Boundary::Tok('a')].into_iter());
+        assert_eq!(mc.lengths.values().sum::<usize>(), 5);
+        assert_eq!(mc.length_percentile(0.), Some(1));
+        assert_eq!(mc.length_percentile(50.), Some(3));
+        assert_eq!(mc.length_percentile(80.), Some(4));
+        assert_eq!(mc.length_percentile(100.), Some(10));
+
+        for _ in 0..50 {
+            assert!(mc.generate_typical_length(1, 80.).len() <= 4);
+        }
+        assert!(mc.try_generate_typical_length(1, 101.).is_err());
+        assert!(mc.try_generate_typical_length(1, f64::NAN).is_err());
+
+        // Merging combines lengths too
+        let mut other = MarkovChain::new_with_rng(1, easy_rng());
+        other.train("a".chars());
+        mc.merge_weighted(&other, 1., 5.);
+        assert_eq!(mc.lengths[&1], 6);
+        assert_eq!(mc.length_percentile(50.), Some(1));
+
+        let csv = "context,next,count\n,a,1\na,a,1\na,,1\n";
+        let mut mc = MarkovChain::<char, ()>::read_csv(1, csv.as_bytes())
+            .unwrap()
+            .with_rng(easy_rng());
+        assert_eq!(mc.length_percentile(50.), None);
+        assert!(!mc.generate_typical_length(1, 50.).is_empty());
+    }
+
+    #[test]
+    fn generate_matching1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for word in ["ab12", "xy7", "b1", "a", "ba9"] {
+            mc.train(word.chars());
         }
-        assert_eq!(stats[&Some('a')], 328);
-        assert_eq!(stats[&Some('b')], 672);
+        let letter = TokenClass::Matching(|c: &char| c.is_ascii_alphabetic());
+        let digit = TokenClass::Matching(|c: &char| c.is_ascii_digit());
+
+        let pattern = [letter.clone(), letter, digit.clone(), digit.clone()];
+        for _ in 0..20 {
+            let term = mc.generate_matching(1, &pattern, 100).unwrap();
+            assert_eq!(term.len(), 4);
+            assert!(term
+                .iter()
+                .zip(pattern.iter())
+                .all(|(t, class)| class.matches(t)));
+        }
+
+        let pattern = [TokenClass::Exact('x'), TokenClass::Any, TokenClass::Any];
+        assert_eq!(
+            mc.generate_matching(1, &pattern, 100),
+            Some(vec!['x', 'y', '7'])
+        );
+        let pattern = [TokenClass::OneOf(BTreeSet::from(['a', 'b']))];
+        let term = mc.generate_matching(1, &pattern, 100).unwrap();
+        assert!(term == vec!['a'] || term == vec!['b']);
+
+        // Nothing ends after `x`
+        assert_eq!(mc.generate_matching(1, &[TokenClass::Exact('x')], 20), None);
+        assert_eq!(mc.generate_matching(1, &[digit], 0), None);
+        assert!(mc.try_generate_matching(2, &[], 1).is_err());
     }
 
     #[test]
-    fn weighted_choice2() {
+    fn generate_smoothed1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train("ab".chars());
+        mc.train("ab".chars());
+
+        for _ in 0..10 {
+            assert_eq!(mc.generate_smoothed(1, 0.), vec!['a', 'b']);
+        }
+        // Nothing was seen after "b" except the end, but now anything can come after it
+        let terms: Vec<Vec<char>> = (0..100)
+            .map(|_| mc.generate_smoothed_with_max_len(1, 1., 10))
+            .collect();
+        assert!(terms.iter().any(|term| term.starts_with(&['b'])));
+        assert!(terms.iter().any(|term| term.is_empty()));
+        assert!(terms.iter().all(|term| term.len() <= 10));
+        assert!(terms.iter().flatten().all(|t| *t == 'a' || *t == 'b'));
+        let terms: Vec<Vec<char>> = (0..100).map(|_| mc.generate_smoothed(1, 1.)).collect();
+        assert!(terms.iter().any(|term| term.starts_with(&['b'])));
+
+        assert!(mc.try_generate_smoothed(1, -1.).is_err());
+        assert!(mc.try_generate_smoothed(2, 1.).is_err());
+        assert!(mc
+            .try_generate_smoothed_with_max_len(1, f64::INFINITY, 10)
+            .is_err());
+    }
+
+    #[test]
+    fn generate_smooth1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for word in ["ax", "ay", "az", "abcd", "q"] {
+            mc.train(word.chars());
+        }
+
+        // [x], [y], [z] and [q] are dead ends
+        let short = |terms: Vec<Vec<char>>| terms.iter().filter(|t| t.len() <= 2).count();
+        let smooth: Vec<_> = (0..100).map(|_| mc.generate_smooth(1)).collect();
+        let plain: Vec<_> = (0..100).map(|_| mc.generate_max_look(1)).collect();
+        assert!(smooth.iter().all(|t| t == &vec!['a', 'b', 'c', 'd']));
+        assert_eq!(short(smooth), 0);
+        assert!(short(plain) > 50);
+
+        // When everything is a dead end, it's chosen anyway
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train("a".chars());
+        assert_eq!(mc.generate_smooth(1), vec!['a']);
+        assert!(mc.try_generate_smooth(2).is_err());
+
+        // The only way out of the loop after 'a' is a dead end
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train("aab".chars());
+        assert_eq!(mc.generate_smooth(1).last(), Some(&'b'));
+    }
+
+    #[test]
+    fn complete1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["pram", "prim", "prom", "trim", "pa"] {
+            mc.train(word.chars());
+        }
+
+        let mut completions = mc.complete(&['p', 'r'], 2, 10, 50);
+        completions.sort();
+        let words = |words: &[&str]| -> Vec<Vec<char>> {
+            words.iter().map(|w| w.chars().collect()).collect()
+        };
+        assert_eq!(completions, words(&["pram", "prim", "prom"]));
+        assert_eq!(mc.complete(&['p', 'r'], 2, 2, 50).len(), 2);
+        // With a lookbehind of 1, `r` can be followed by anything after `r`
+        assert!(mc
+            .complete(&['t'], 1, 10, 50)
+            .contains(&vec!['t', 'r', 'i', 'm']));
+        assert!(mc.complete(&[], 2, 10, 50).contains(&vec!['p', 'a']));
+        assert!(mc.try_complete(&[], 3, 10, 50).is_err());
+    }
+
+    #[test]
+    fn generate_from_prefix1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["pram", "prim", "prom", "trim", "pa"] {
+            mc.train(word.chars());
+        }
+
+        let string = |word: Vec<char>| -> String { word.into_iter().collect() };
+        for _ in 0..10 {
+            let word = string(mc.generate_from_prefix(&['p', 'r'], 2));
+            assert!(
+                ["pram", "prim", "prom"].contains(&word.as_str()),
+                "{}",
+                word
+            );
+            // Only the end of a long prefix matters
+            let word = string(mc.generate_from_prefix(&['x', 'y', 'p', 'r'], 2));
+            assert!(word.starts_with("xypr") && word.ends_with('m'), "{}", word);
+            // "zr" was never seen, so it carries on after "r"
+            let word = string(mc.generate_from_prefix(&['z', 'r'], 2));
+            assert!(word.starts_with("zr") && word.ends_with('m'), "{}", word);
+        }
+        assert_eq!(mc.generate_from_prefix(&['p', 'a'], 2), vec!['p', 'a']);
+        assert!(mc.try_generate_from_prefix(&['p'], 3).is_err());
+    }
+
+    #[test]
+    fn distinct_terms1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["ab", "ac", "ad"] {
+            mc.train(word.chars());
+        }
+
+        let mut terms = mc.distinct_terms(2, 10, 50);
+        terms.sort();
+        assert_eq!(terms, vec![vec!['a', 'b'], vec!['a', 'c'], vec!['a', 'd']]);
+        assert_eq!(mc.distinct_terms(2, 2, 50).len(), 2);
+        assert!(mc.distinct_terms(2, 10, 0).is_empty());
+        assert!(mc.try_distinct_terms(3, 10, 50).is_err());
+    }
+
+    #[test]
+    fn generate_diverse1() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(edit_distance(&chars("brun"), &chars("brunda")), 2);
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(edit_distance(&chars(""), &chars("abc")), 3);
+        assert_eq!(edit_distance(&chars("abc"), &chars("abc")), 0);
+
+        let mut mc = MarkovChain::new(2);
+        for word in ["brun", "brund", "brunda", "kel", "kelso", "tam"] {
+            mc.train(word.chars());
+        }
+        let terms = mc.generate_diverse(2, 5, 3, 200);
+        assert!(!terms.is_empty() && terms.len() <= 5);
+        for (i, a) in terms.iter().enumerate() {
+            for b in &terms[i + 1..] {
+                assert!(edit_distance(a, b) >= 3);
+            }
+        }
+        assert_eq!(mc.generate_diverse(2, 5, 100, 20).len(), 1);
+        assert!(mc.try_generate_diverse(3, 5, 3, 20).is_err());
+    }
+
+    #[test]
+    fn generate_constrained1() {
+        let mut mc = MarkovChain::new(1);
+        for word in ["ab", "eb", "ba", "be", "bib"] {
+            mc.train(word.chars());
+        }
+        let has_a_and_e = |term: &[char]| term.contains(&'a') && term.contains(&'e');
+        for _ in 0..20 {
+            let term = mc.generate_constrained(1, has_a_and_e, 5, 100).unwrap();
+            assert!(has_a_and_e(&term));
+            assert!(term.len() <= 5);
+            assert!(mc.can_generate(&term));
+        }
+
+        // "abe" and "eba" are the shortest
+        let shortest = mc.generate_constrained(1, has_a_and_e, 3, 1000).unwrap();
+        assert!(shortest == ['a', 'b', 'e'] || shortest == ['e', 'b', 'a']);
+        assert_eq!(mc.generate_constrained(1, has_a_and_e, 2, 1000), None);
+        assert_eq!(
+            mc.generate_constrained(1, |t| t.contains(&'z'), 10, 5),
+            None
+        );
+        assert!(mc.try_generate_constrained(2, has_a_and_e, 5, 5).is_err());
+    }
+
+    #[test]
+    fn generate_order_window1() {
+        let csv = "context,next,count\n,a,1\n|a,b,1\nb,c,1\nc,,1\n";
+        let mut mc = MarkovChain::<char, ()>::read_csv(2, csv.as_bytes())
+            .unwrap()
+            .with_rng(easy_rng());
+
+        // [a, b] was never seen, so falling back to [b] is needed to carry on
+        assert_eq!(mc.generate_order_window(1, 2), vec!['a', 'b', 'c']);
+        assert_eq!(mc.generate_order_window(2, 2), vec!['a', 'b']);
+        // With a lookbehind of 1, [a] was never seen either
+        assert_eq!(mc.generate_order_window(1, 1), vec!['a']);
+
+        assert!(mc.try_generate_order_window(0, 2).is_err());
+        assert!(mc.try_generate_order_window(2, 1).is_err());
+        assert!(mc.try_generate_order_window(1, 3).is_err());
+    }
+
+    #[test]
+    fn start_mode1() {
+        // Most terms which start with 'a' are just "a", so the longer ones rarely start with it
+        let mut mc = MarkovChain::new_with_rng(3, easy_rng());
+        for _ in 0..20 {
+            mc.train("a".chars());
+        }
+        mc.train("abc".chars());
+        mc.train("xyz".chars());
+        mc.train("xyw".chars());
+        let starts_ab = |mc: &mut MarkovChain<char, _>| {
+            (0..300)
+                .filter(|_| mc.generate_max_look(3).starts_with(&['a', 'b']))
+                .count()
+        };
+        assert!(starts_ab(&mut mc) < 30);
+
+        mc.set_start_mode(StartMode::Openings);
+        let openings = starts_ab(&mut mc);
+        assert!(openings > 70 && openings < 130);
+        let term: String = mc.generate_collect(3);
+        assert!(term.starts_with("ab") || term.starts_with("xy"));
+
+        // Nothing was long enough to have an opening of 2 items
+        let mut mc = MarkovChain::new_with_rng(3, easy_rng());
+        mc.train("a".chars());
+        mc.set_start_mode(StartMode::Openings);
+        assert_eq!(mc.generate(), vec!['a']);
+        assert!(mc.openings.contains_key(&3));
+
+        // Training changes the openings, so they're found again
+        mc.train("bcd".chars());
+        assert!(mc.openings.is_empty());
+        assert_eq!(mc.generate(), vec!['b', 'c', 'd']);
+    }
+
+    #[test]
+    fn generate_budget1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        for word in ["abc", "de", "fghij", ""] {
+            mc.train(word.chars());
+        }
+        let len = |terms: &[Vec<char>]| terms.iter().map(Vec::len).sum::<usize>();
+        for _ in 0..20 {
+            let terms = mc.generate_budget(2, 12, BudgetOverflow::Truncate, 50);
+            assert_eq!(len(&terms), 12);
+            assert!(terms.iter().all(|term| !term.is_empty()));
+
+            let terms = mc.generate_budget(2, 12, BudgetOverflow::Discard, 50);
+            assert!(len(&terms) <= 12);
+            assert!(terms.iter().all(|term| mc.can_generate(term)));
+        }
+        assert!(mc
+            .generate_budget(2, 0, BudgetOverflow::Truncate, 50)
+            .is_empty());
+
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train("".chars());
+        assert!(mc
+            .generate_budget(2, 5, BudgetOverflow::Truncate, 10)
+            .is_empty());
+        assert!(mc
+            .try_generate_budget(3, 5, BudgetOverflow::Truncate, 10)
+            .is_err());
+    }
+
+    #[test]
+    fn generate_recorded1() {
+        let train = || {
+            let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+            for word in ["rust", "trust", "crust", "bust", "dust"] {
+                mc.train(word.chars());
+            }
+            mc
+        };
+        let mut recorder = train();
+        let mut plain = train();
+        for _ in 0..50 {
+            let (term, draws) = recorder.generate_recorded(2);
+            assert_eq!(term, plain.generate_max_look(2));
+            assert_eq!(draws.len(), term.len() + 1);
+            assert_eq!(recorder.replay(&draws, 2), term);
+            assert_eq!(plain.replay(&draws[..1], 2), term[..1].to_vec());
+        }
+        assert_eq!(plain.replay(&[], 2), Vec::<char>::new());
+        // Too big for every stage, so always the last next item
+        assert_eq!(
+            plain.replay(&[u32::MAX; 10], 2),
+            "trust".chars().collect::<Vec<_>>()
+        );
+        assert!(plain.try_replay(&[], 3).is_err());
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn try_generate_recorded_too_big() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train_counted([(vec!['a'], 1 << 40)]);
+        assert_eq!(
+            mc.try_generate_recorded(2),
+            Err(Error::InvalidArgument("counts are too big to record"))
+        );
+    }
+
+    #[test]
+    fn from_seed1() {
+        let train = |seed| {
+            let mut mc = MarkovChain::from_seed(2, seed);
+            for word in ["rust", "trust", "crust", "bust", "dust"] {
+                mc.train(word.chars());
+            }
+            mc
+        };
+        let (mut mc, mut same) = (train(7), train(7));
+        let terms: Vec<Vec<char>> = (0..20).map(|_| mc.generate()).collect();
+        assert!(terms.iter().all(|term| *term == same.generate()));
+        let mut other = train(8);
+        assert!(terms.iter().any(|term| *term != other.generate()));
+
+        mc.reseed(7);
+        assert!(terms.iter().all(|term| *term == mc.generate()));
+        assert!(MarkovChain::<char, _>::try_from_seed(0, 7).is_err());
+    }
+
+    #[test]
+    fn clone1() {
+        let mut mc = MarkovChain::new_with_rng(2, rand::prng::XorShiftRng::from_seed([3; 16]));
+        for word in ["rust", "trust", "crust", "bust", "dust"] {
+            mc.train(word.chars());
+        }
+        let mut variant = mc.clone();
+        for _ in 0..20 {
+            assert_eq!(mc.generate(), variant.generate());
+        }
+
+        variant.untrain("bust".chars());
+        assert!(has_key_w_none_prefix(&mc, "b"));
+        assert!(!has_key_w_none_prefix(&variant, "b"));
+        let untrained = MarkovChain::<char, ()>::without_rng(1).clone();
+        assert!(untrained.stages.is_empty());
+    }
+
+    #[test]
+    fn untrain1() {
+        let mut mc = MarkovChain::new_with_rng(3, easy_rng());
+        mc.train("banana".chars());
+        assert!(mc.untrain("banana".chars()));
+        assert!(mc.stages.is_empty());
+        assert_eq!(mc.alphabet, (0, BTreeMap::new()));
+        assert!(mc.lengths.is_empty());
+
+        let mut only_ac = MarkovChain::without_rng(3);
+        only_ac.train("ac".chars());
+        for word in ["ab", "ac", "ab"] {
+            mc.train(word.chars());
+        }
+        assert_eq!(mc.generate(), vec!['a', 'b']);
+        assert!(mc.untrain("ab".chars()));
+        assert!(mc.untrain("ab".chars()));
+        assert_eq!(mc.stages, only_ac.stages);
+        assert_eq!(mc.alphabet, only_ac.alphabet);
+        assert!(mc.validate().is_ok());
+        for _ in 0..10 {
+            assert_eq!(mc.generate(), vec!['a', 'c']);
+        }
+
+        // Never trained, so nothing changes
+        assert!(!mc.untrain("ab".chars()));
+        assert!(!mc.untrain("acc".chars()));
+        assert_eq!(mc.stages, only_ac.stages);
+    }
+
+    #[test]
+    fn sampled_tokens1() {
+        let mut rng = rand::prng::XorShiftRng::from_seed([3; 16]);
+        let mut mc = MarkovChain::new_with_rng(2, rng.clone());
+        for word in ["rust", "trust", "crust", "bust", "dust", "busts"] {
+            mc.train(word.chars());
+        }
+        // The same choices as going through the next items of each stage
+        for _ in 0..50 {
+            let uncached = generate_term(&mc.stages, &mc.alphabet, &mut rng, 2);
+            assert_eq!(mc.generate_max_look(2), uncached);
+        }
+        assert!(!mc.cumulative.is_empty());
+
+        // Training changes the stages, so they're worked out again
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train("ab".chars());
+        assert_eq!(mc.generate(), vec!['a', 'b']);
+        for _ in 0..100 {
+            mc.train("ac".chars());
+        }
+        assert!((0..10).any(|_| mc.generate() == vec!['a', 'c']));
+    }
+
+    #[test]
+    fn unreachable_tokens1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("ab".chars());
+        mc.train("cab".chars());
+        assert!(mc.unreachable_tokens().is_empty());
+
+        // Only "c" came right at the start, which is a stage of 1 item
+        let extracted = mc.extract_order(2);
+        assert_eq!(extracted.unreachable_tokens(), BTreeSet::from(['c']));
+    }
+
+    #[test]
+    fn dead_end_contexts1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("ab".chars());
+        mc.train("cb".chars());
+        mc.train("ca".chars());
+
+        assert_eq!(
+            mc.dead_end_contexts(),
+            vec![stage("ab"), stage("b"), stage("ca"), stage("cb")]
+        );
+        // Generating with a lookbehind of 2 never uses [a] or [b] on their own. Falling back
+        // to [a] only happens with a lookbehind of 1.
+        assert_eq!(
+            mc.reachable_contexts(),
+            vec![
+                vec![Boundary::Start],
+                vec![Boundary::Start, Boundary::Tok('a')],
+                vec![Boundary::Start, Boundary::Tok('c')],
+                stage("ab"),
+                stage("ca"),
+                stage("cb"),
+            ]
+        );
+        assert!(MarkovChain::<char, ()>::without_rng(2)
+            .reachable_contexts()
+            .is_empty());
+    }
+
+    #[test]
+    fn has_context1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        assert!(mc.has_context(&['a']));
+        assert!(mc.has_context(&['a', 'b']));
+        assert!(mc.has_context(&['c']));
+        assert!(!mc.has_context(&['b', 'a']));
+        assert!(!mc.has_context(&['a', 'b', 'c']));
+        assert!(!mc.has_context(&[]));
+    }
+
+    #[test]
+    fn generate_from_key1() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+
+        let mut mc = MarkovChain::without_rng(2);
+        for word in ["rust", "trust", "crust", "bust", "dust", "must"] {
+            mc.train(word.chars());
+        }
+        assert_eq!(
+            mc.generate_from_key("order-1234", 2),
+            mc.generate_from_key("order-1234", 2)
+        );
+        assert_eq!(
+            mc.generate_from_key("order-1234", 2),
+            mc.nth(fnv1a(b"order-1234"), 2)
+        );
+        let terms: BTreeSet<_> = (0..20)
+            .map(|i| mc.generate_from_key(&format!("order-{}", i), 2))
+            .collect();
+        assert!(terms.len() > 1);
+        assert!(MarkovChain::<char, ()>::without_rng(2)
+            .try_generate_from_key("a", 2)
+            .is_err());
+    }
+
+    #[test]
+    fn equivalent_context_groups1() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("xab".chars());
+        mc.train("yab".chars());
+
+        // [x, a], [y, a] and [a] are always followed by `b`, and most others by the end
+        let groups = mc.equivalent_context_groups();
+        assert!(groups.contains(&vec![stage("a"), stage("xa"), stage("ya")]));
+        assert!(groups.contains(&vec![stage("ab"), stage("b")]));
+        // Each stage is only in one group
+        let grouped: BTreeSet<_> = groups.iter().flatten().collect();
+        assert_eq!(grouped.len(), groups.iter().map(|g| g.len()).sum::<usize>());
+    }
+
+    #[test]
+    fn prune1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for word in ["rust", "trust", "crust", "bust", "dust", "rusk", "ax"] {
+            mc.train(word.chars());
+        }
+        let before = mc.stages.len();
+        assert!(mc.prune(2) > 0);
+        assert!(mc.stages.len() < before);
+        assert!(mc.validate().is_ok());
+        assert!(mc
+            .stages
+            .values()
+            .all(|stats| stats.1.values().all(|count| *count >= 2)));
+        // "k" was only seen once, after "s", and most items only started one term
+        assert!(!has_key(&mc, "k"));
+        assert_eq!(
+            mc.unreachable_tokens(),
+            BTreeSet::from(['a', 'b', 'c', 'd', 'k', 'x'])
+        );
+        for _ in 0..100 {
+            mc.generate();
+            mc.generate_exact_len(1, 4, 10);
+        }
+        assert_eq!(mc.prune(2), 0);
+        assert_eq!(mc.prune(0), 0);
+    }
+
+    #[test]
+    fn collapse_equivalent1() {
+        let mut mc = MarkovChain::new_with_rng(3, easy_rng());
+        for word in ["xabc", "yabd", "zab"] {
+            mc.train(word.chars());
+        }
+        let mut collapsed = MarkovChain::new_with_rng(3, easy_rng());
+        for word in ["xabc", "yabd", "zab"] {
+            collapsed.train(word.chars());
+        }
+
+        let removed = collapsed.collapse_equivalent(0.);
+        assert!(removed > 0);
+        assert_eq!(collapsed.stages.len(), mc.stages.len() - removed);
+        assert!(!has_key(&collapsed, "xa"));
+        assert!(has_key(&collapsed, "a"));
+        assert!(has_key(&collapsed, "xab"));
+        for term in [&['x', 'a', 'b'][..], &['y', 'a', 'b', 'd'], &['z', 'a']] {
+            assert_eq!(collapsed.predict_next(term), mc.predict_next(term));
+        }
+        // Nothing left is exactly the same as its fall back
+        assert_eq!(collapsed.collapse_equivalent(0.), 0);
+
+        // [a, b] is followed by `c`, `d` or the end, which is close enough to [b]
+        let mut close = MarkovChain::without_rng(2);
+        for word in ["abc", "abd", "ab", "b", "bc", "bd", "bd"] {
+            close.train(word.chars());
+        }
+        let mut exact = MarkovChain::without_rng(2);
+        for word in ["abc", "abd", "ab", "b", "bc", "bd", "bd"] {
+            exact.train(word.chars());
+        }
+        exact.collapse_equivalent(0.);
+        assert!(has_key(&exact, "ab"));
+        close.collapse_equivalent(0.1);
+        assert!(!has_key(&close, "ab"));
+
+        assert!(mc.try_collapse_equivalent(-1.).is_err());
+    }
+
+    #[test]
+    fn update1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.update("ab".chars(), 0.5);
+        assert_eq!(mc.stages[&vec![Boundary::Start]].0, 1);
+        for _ in 0..99 {
+            mc.train("ab".chars());
+        }
+
+        mc.update("ac".chars(), 0.1);
+        let start = &mc.stages[&vec![Boundary::Start]];
+        assert_eq!(start.0, 100);
+        let a = &mc.stages[&stage("a")];
+        assert_eq!(a.1[&Boundary::Tok('b')], 90);
+        assert_eq!(a.1[&Boundary::Tok('c')], 10);
+        assert_eq!(a.0, 100);
+        assert_eq!(mc.alphabet.0, mc.alphabet.1.values().sum::<usize>());
+        assert_eq!(mc.alphabet.1[&'c'], 10);
+
+        // Everything old is forgotten
+        mc.update("d".chars(), 1.);
+        assert!(!has_key(&mc, "a"));
+        assert_eq!(
+            mc.stages[&vec![Boundary::Start]].1[&Boundary::Tok('d')],
+            100
+        );
+
+        assert!(mc.try_update("a".chars(), 1.5).is_err());
+        assert!(mc.try_update("a".chars(), f64::NAN).is_err());
+    }
+
+    #[test]
+    fn rounding_mode1() {
+        let rng = &mut easy_rng();
+        assert_eq!(RoundingMode::Floor.round(2.9, rng), 2);
+        assert_eq!(RoundingMode::Round.round(2.5, rng), 3);
+        assert_eq!(RoundingMode::Round.round(0.4, rng), 0);
+        assert_eq!(RoundingMode::Ceil.round(0.1, rng), 1);
+        assert_eq!(RoundingMode::Ceil.round(2., rng), 2);
+        assert_eq!(RoundingMode::default(), RoundingMode::Ceil);
+        let ups = (0..1000)
+            .filter(|_| RoundingMode::StochasticRound.round(4.25, rng) == 5)
+            .count();
+        assert!(ups > 150 && ups < 350);
+        assert_eq!(RoundingMode::StochasticRound.round(3., rng), 3);
+
+        let train = || {
+            let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+            for _ in 0..9 {
+                mc.train("ab".chars());
+            }
+            mc.train("ac".chars());
+            mc
+        };
+        // Halving the only `ac` is 0.5, which rounds either way
+        let mut rounded = train();
+        rounded.merge_weighted_with_rounding(&train(), 0.25, 0.25, RoundingMode::Round);
+        assert_eq!(
+            rounded.stages[&stage("a")].1.get(&Boundary::Tok('c')),
+            Some(&1)
+        );
+        let mut floored = train();
+        floored.merge_weighted_with_rounding(&train(), 0.25, 0.25, RoundingMode::Floor);
+        assert_eq!(floored.stages[&stage("a")].1.get(&Boundary::Tok('c')), None);
+        assert_eq!(floored.stages[&stage("a")].0, 4);
+
+        let mut ceil = train();
+        for _ in 0..20 {
+            ceil.update_with_rounding("ab".chars(), 0.6, RoundingMode::Ceil);
+        }
+        assert_eq!(ceil.stages[&stage("a")].1[&Boundary::Tok('c')], 1);
+        let mut round = train();
+        for _ in 0..20 {
+            round.update_with_rounding("ab".chars(), 0.6, RoundingMode::Round);
+        }
+        assert!(!round.stages[&stage("a")]
+            .1
+            .contains_key(&Boundary::Tok('c')));
+
+        // The plain methods use the mode of the chain, which keeps rare items by default
+        let mut plain = train();
+        for _ in 0..20 {
+            plain.update("ab".chars(), 0.6);
+        }
+        assert_eq!(plain.stages, ceil.stages);
+        let mut plain = train();
+        plain.set_rounding_mode(RoundingMode::Round);
+        for _ in 0..20 {
+            plain.update("ab".chars(), 0.6);
+        }
+        assert_eq!(plain.stages, round.stages);
+
+        // Stochastic rounding uses the random number generator of the chain
+        let stochastic = || {
+            let mut mc = train();
+            mc.set_rounding_mode(RoundingMode::StochasticRound);
+            for _ in 0..20 {
+                mc.update("ab".chars(), 0.3);
+                mc.merge_weighted(&train(), 0.7, 0.1);
+            }
+            mc.quantize(3);
+            mc
+        };
+        assert_eq!(stochastic().stages, stochastic().stages);
+
+        assert!(ceil
+            .try_merge_weighted_with_rounding(&train(), -1., 1., RoundingMode::Ceil)
+            .is_err());
+        assert!(ceil
+            .try_update_with_rounding("a".chars(), 2., RoundingMode::Ceil)
+            .is_err());
+    }
+
+    #[test]
+    fn quantize1() {
+        let train = || {
+            let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+            for (i, word) in ["ab", "ac", "ad", "bad", "cad", "dab"].iter().enumerate() {
+                for _ in 0..(i * i * 13 + i * 3 + 1) {
+                    mc.train(word.chars());
+                }
+            }
+            mc
+        };
+        let original = train();
+        let mut mc = train();
+        mc.quantize(8);
+
+        fn values<R>(mc: &MarkovChain<char, R>) -> BTreeSet<usize> {
+            mc.stages
+                .values()
+                .flat_map(|stats| stats.1.values().copied())
+                .chain(mc.alphabet.1.values().copied())
+                .collect()
+        }
+        assert!(values(&original).len() > 8);
+        let values = values(&mc);
+        assert!(values.len() <= 8);
+        assert!(!values.contains(&0));
+        assert_eq!(mc.alphabet.0, mc.alphabet.1.values().sum::<usize>());
+        for stats in mc.stages.values() {
+            assert_eq!(stats.0, stats.1.values().sum::<usize>());
+        }
+        assert_eq!(mc.stages.len(), original.stages.len());
+
+        // The probabilities change, but not by much
+        let before = original.predict_next(&['a']);
+        let after = mc.predict_next(&['a']);
+        assert_eq!(
+            before.keys().collect::<Vec<_>>(),
+            after.keys().collect::<Vec<_>>()
+        );
+        for (next, p) in before.iter() {
+            assert!(
+                (p - after[next]).abs() < 0.15,
+                "{:?} {} {}",
+                next,
+                p,
+                after[next]
+            );
+        }
+        assert!(original.kl_divergence(&mc) < 0.05);
+
+        assert!(mc.try_quantize(0).is_err());
+    }
+
+    #[test]
+    fn generate_annealed1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for _ in 0..9 {
+            mc.train("ab".chars());
+        }
+        mc.train("ac".chars());
+
+        // Hot at the start, cold from the second item, so `c` almost never comes
+        let cold: usize = (0..100)
+            .filter(|_| mc.generate_annealed(1, 1., 0.01, 1, 10) == vec!['a', 'c'])
+            .count();
+        assert_eq!(cold, 0);
+        // The other way round, `b` and `c` are about as likely
+        let hot: usize = (0..200)
+            .filter(|_| mc.generate_annealed(1, 0.01, 1000., 1, 10) == vec!['a', 'c'])
+            .count();
+        assert!(hot > 60 && hot < 140);
+
+        assert!(mc.try_generate_annealed(1, 0., 1., 5, 10).is_err());
+        assert!(mc
+            .try_generate_annealed(1, 1., f64::INFINITY, 5, 10)
+            .is_err());
+    }
+
+    #[test]
+    fn generate_with_temperature1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for _ in 0..9 {
+            mc.train("ab".chars());
+        }
+        mc.train("ac".chars());
+
+        for _ in 0..10 {
+            assert_eq!(mc.generate_with_temperature(1, 0., 10), vec!['a', 'b']);
+        }
+        let cold = (0..100)
+            .filter(|_| mc.generate_with_temperature(1, 0.01, 10) == vec!['a', 'c'])
+            .count();
+        assert_eq!(cold, 0);
+        let hot = (0..200)
+            .filter(|_| mc.generate_with_temperature(1, 1000., 10) == vec!['a', 'c'])
+            .count();
+        assert!(hot > 60 && hot < 140);
+
+        assert!(mc.try_generate_with_temperature(1, -1., 10).is_err());
+        assert!(mc.try_generate_with_temperature(1, f64::NAN, 10).is_err());
+        assert!(mc.try_generate_with_temperature(1, 0., 10).is_ok());
+    }
+
+    #[test]
+    fn generate_positional_temp1() {
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        for _ in 0..9 {
+            mc.train("ab".chars());
+        }
+        mc.train("cb".chars());
+
+        // Cold for the first item, so `c` almost never starts the term
+        let starts_with_c = (0..100)
+            .filter(|_| mc.generate_positional_temp(1, &[0.01, 1.], 10)[0] == 'c')
+            .count();
+        assert_eq!(starts_with_c, 0);
+        let starts_with_c = (0..200)
+            .filter(|_| mc.generate_positional_temp(1, &[1000.], 10)[0] == 'c')
+            .count();
+        assert!(starts_with_c > 60 && starts_with_c < 140);
+
+        assert!(mc.try_generate_positional_temp(1, &[], 10).is_err());
+        assert!(mc.try_generate_positional_temp(1, &[1., -1.], 10).is_err());
+        assert!(mc.try_generate_positional_temp(2, &[1.], 10).is_err());
+    }
+
+    #[test]
+    fn tempered_choice1() {
         let mut rng = easy_rng();
-        let mut data = BTreeMap::new();
-        data.insert(Some('a'), 1);
-        data.insert(Some('b'), 200);
-        data.insert(Some('c'), 200);
-        data.insert(Some('d'), 200);
-        let data = (data.values().sum(), data);
-        let mut stats: HashMap<Option<char>, usize> = HashMap::new();
+        let counts = BTreeMap::from([('a', 1), ('b', 1000)]);
+        assert_eq!(tempered_choice(&mut rng, &counts, 1e-6), Some('b'));
+        assert_eq!(
+            tempered_choice(&mut rng, &BTreeMap::<char, usize>::new(), 1.),
+            None
+        );
+        let a = (0..1000)
+            .filter(|_| tempered_choice(&mut rng, &counts, 1.) == Some('a'))
+            .count();
+        assert!(a < 10);
+    }
 
-        for _ in 0..10_000 {
-            let choice = weighted_choice(&mut rng, &data);
-            *stats.entry(choice).or_default() += 1;
+    #[test]
+    fn generate_authentic_start1() {
+        let mut mc = MarkovChain::new_with_rng(3, easy_rng());
+        for word in ["abc", "abd", "xbc", "b"] {
+            mc.train(word.chars());
         }
-        assert_eq!(stats[&Some('a')], 15);
-        assert_eq!(stats[&Some('b')], 3293);
-        assert_eq!(stats[&Some('c')], 3405);
-        assert_eq!(stats[&Some('d')], 3287);
+        let mut openings = BTreeMap::new();
+        for _ in 0..200 {
+            let term = mc.generate_authentic_start(3);
+            assert!(term.len() >= 2);
+            assert!(mc.can_generate(&term));
+            *openings.entry(term[..2].to_vec()).or_insert(0) += 1;
+        }
+        assert_eq!(openings.len(), 2);
+        assert!(openings[&vec!['a', 'b']] > openings[&vec!['x', 'b']]);
+
+        // With a lookbehind of 1, the opening is only the start
+        let mut mc = MarkovChain::new_with_rng(1, easy_rng());
+        mc.train("ab".chars());
+        assert_eq!(mc.generate_authentic_start(1), vec!['a', 'b']);
     }
 
-    fn prediction_result<R: Rng>(mc: &mut MarkovChain<char, R>) -> String {
-        mc.generate()
-            .into_iter()
-            .map(|c| c.to_string())
-            .collect::<Vec<String>>()
-            .join("")
+    #[test]
+    fn length_histogram1() {
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        mc.train("ab".chars());
+        mc.train("cd".chars());
+        assert_eq!(mc.length_histogram(100, 2), BTreeMap::from([(2, 100)]));
+
+        mc.train("abcd".chars());
+        let histogram = mc.length_histogram(100, 2);
+        assert_eq!(histogram.values().sum::<usize>(), 100);
+        assert!(histogram.keys().all(|len| *len >= 2));
     }
 
-    fn prediction_result_size<R: Rng>(mc: &mut MarkovChain<char, R>, size: usize) -> String {
-        mc.generate_max_look(size)
-            .into_iter()
-            .map(|c| c.to_string())
-            .collect::<Vec<String>>()
-            .join("")
+    #[test]
+    fn nth1() {
+        let mut mc = MarkovChain::without_rng(3);
+        for word in ["abc", "bbc", "acb", "rust", "trust", "crust", "bust"] {
+            mc.train(word.chars());
+        }
+        let mut other = MarkovChain::without_rng(3);
+        for word in ["abc", "bbc", "acb", "rust", "trust", "crust", "bust"] {
+            other.train(word.chars());
+        }
+
+        let words: Vec<Vec<char>> = (0..20).map(|n| mc.nth(n, 3)).collect();
+        assert_eq!(words, (0..20).map(|n| other.nth(n, 3)).collect::<Vec<_>>());
+        assert_eq!(words[5], mc.generate_with_rng(&mut seeded_rng(5), 3));
+        assert!(words.iter().collect::<BTreeSet<_>>().len() > 1);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn generate_many_parallel1() {
+        let mut mc = MarkovChain::new(3);
+        for word in ["abc", "bbc", "acb", "rust", "trust", "crust", "bust"] {
+            mc.train(word.chars());
+        }
+
+        let words = mc.generate_many_parallel(100, 3, 42);
+        assert_eq!(words.len(), 100);
+        assert_eq!(words, mc.generate_many_parallel(100, 3, 42));
+        // Term 10 with base seed 42 is term 0 with base seed 52
+        assert_eq!(words[10..], mc.generate_many_parallel(90, 3, 52)[..]);
+
+        let sequential: Vec<Vec<char>> = (0..100)
+            .map(|i| mc.generate_with_rng(&mut seeded_rng(42 + i), 3))
+            .collect();
+        assert_eq!(words, sequential);
+    }
+
+    #[test]
+    fn untrained_generate() {
+        let mut mc: MarkovChain<char, _> = MarkovChain::new(2);
+        assert_eq!(mc.try_generate(), Err(Error::Untrained));
+        assert_eq!(mc.try_generate_string(2), Err(Error::Untrained));
+        assert_eq!(mc.try_generate_smooth(2), Err(Error::Untrained));
+        assert_eq!(mc.try_distinct_terms(2, 5, 5), Err(Error::Untrained));
+        assert_eq!(mc.try_generate_diverse(2, 5, 1, 5), Err(Error::Untrained));
+        assert_eq!(
+            mc.try_generate_budget(2, 10, BudgetOverflow::Truncate, 5),
+            Err(Error::Untrained)
+        );
+        assert_eq!(mc.try_complete(&['a'], 2, 5, 5), Err(Error::Untrained));
+        assert_eq!(mc.try_replay(&[0, 0], 2), Err(Error::Untrained));
+        // The methods without `try_` give empty terms instead
+        assert_eq!(mc.generate_smooth(2), vec![]);
+        // Nothing to choose from is the end of the term, rather than a panic in `rand`
+        assert_eq!(
+            weighted_choice_counts(&mut mc.rng, &mc.alphabet),
+            char::default()
+        );
+        assert_eq!(
+            weighted_choice(&mut mc.rng, &BTreeMap::<char, usize>::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn try_methods() {
+        assert_eq!(
+            MarkovChain::<char, _>::try_new(0).err(),
+            Some(Error::ZeroSize)
+        );
+        assert!(MarkovChain::<char, ()>::try_without_rng(1).is_ok());
+
+        let mut mc = MarkovChain::new_with_rng(2, easy_rng());
+        assert_eq!(mc.try_generate(), Err(Error::Untrained));
+        assert_eq!(
+            mc.try_generate_greedy(2, 10, TieBreak::First),
+            Err(Error::Untrained)
+        );
+        mc.train("ab".chars());
+        assert_eq!(mc.try_generate(), Ok(vec!['a', 'b']));
+        assert_eq!(
+            mc.try_generate_max_look(3),
+            Err(Error::InvalidLookbehind {
+                max_lookbehind: 3,
+                size: 2
+            })
+        );
+        assert!(mc.try_nth(1, 0).is_err());
+        assert!(mc.try_generate_rank_limited(2, 0).is_err());
+        assert_eq!(mc.try_generate_string(1).unwrap(), "ab");
+
+        // Nothing is learnt from a term with a misplaced boundary
+        let term = vec![Boundary::Tok('c'), Boundary::Start];
+        assert_eq!(
+            mc.try_train_with_boundaries(term.into_iter()),
+            Err(Error::MisplacedBoundary)
+        );
+        assert_eq!(mc.alphabet.0, 2);
+
+        let other = MarkovChain::new_with_rng(3, easy_rng());
+        assert!(mc.try_merge_weighted(&other, 1., 1.).is_err());
+        let other = MarkovChain::new_with_rng(2, easy_rng());
+        assert!(mc.try_merge_weighted(&other, -1., 1.).is_err());
+        assert!(mc.try_merge_weighted(&other, 1., 1.).is_ok());
+
+        assert_eq!(
+            Error::InvalidLookbehind {
+                max_lookbehind: 3,
+                size: 2
+            }
+            .to_string(),
+            "lookbehind 3 must be between 1 and the lookbehind of the chain, 2"
+        );
     }
 
     #[test]
@@ -0,0 +1,229 @@
+//! Generating with a random choice of ways to generate.
+
+use super::{choose_by_weight, Error, MarkovChain, Result, TieBreak};
+use rand::Rng;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// One way of generating a term, for a [`MixtureGenerator`]. Each is the `generate_` method of
+/// the same name, with these arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// `generate_max_look`.
+    Sample,
+    /// `generate_greedy`, the most likely item each time.
+    Greedy {
+        /// The most items in a term.
+        max_len: usize,
+        /// How to choose between equally likely items.
+        tie_break: TieBreak,
+    },
+    /// `generate_rank_limited`, only choosing from the `top_rank` most likely items each time.
+    RankLimited {
+        /// How many of the most likely items to choose from, at least 1.
+        top_rank: usize,
+    },
+    /// `generate_confident`, ignoring next items seen fewer than `min_count` times after their
+    /// stage.
+    Confident {
+        /// How often a next item must have been seen after its stage to be chosen.
+        min_count: usize,
+        /// The most items in a term.
+        max_len: usize,
+    },
+    /// `generate_smooth`, avoiding items which lead straight to the end.
+    Smooth,
+    /// `generate_authentic_start`, starting like a training term.
+    AuthenticStart,
+}
+
+/// Generates terms from a chain, choosing a [`Strategy`] at random for each term, e.g. mostly
+/// sampling, with some greedy terms, to get a mix of usual and unusual terms.
+///
+/// Made with [`MarkovChain::mixture`]. The strategies are chosen with the random number
+/// generator of the chain, as are the terms, so a chain with a seeded generator always gives
+/// the same terms, from the same strategies.
+pub struct MixtureGenerator<'a, T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    chain: &'a mut MarkovChain<T, R>,
+    max_lookbehind: usize,
+    strategies: Vec<(Strategy, f64)>,
+}
+
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng,
+{
+    /// Starts generating terms with a custom lookbehind, each with one of `strategies`, chosen
+    /// with a probability proportional to its weight. Strategies with a weight of 0 are never
+    /// chosen.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with, if a
+    /// weight is negative or not finite, if no weight is more than 0, or if a
+    /// `Strategy::RankLimited` has a `top_rank` of 0.
+    pub fn mixture(
+        &mut self,
+        max_lookbehind: usize,
+        strategies: Vec<(Strategy, f64)>,
+    ) -> MixtureGenerator<'_, T, R> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        if let Err(e) = check_strategies(&strategies) {
+            panic!("{}", e);
+        }
+        MixtureGenerator {
+            chain: self,
+            max_lookbehind,
+            strategies,
+        }
+    }
+
+    /// Like `mixture`, but returns an error if `max_lookbehind` or `strategies` are invalid, or
+    /// this chain hasn't been trained.
+    pub fn try_mixture(
+        &mut self,
+        max_lookbehind: usize,
+        strategies: Vec<(Strategy, f64)>,
+    ) -> Result<MixtureGenerator<'_, T, R>> {
+        self.check_can_generate(max_lookbehind)?;
+        check_strategies(&strategies)?;
+        Ok(self.mixture(max_lookbehind, strategies))
+    }
+}
+
+fn check_strategies(strategies: &[(Strategy, f64)]) -> Result<()> {
+    if strategies
+        .iter()
+        .any(|(_, weight)| !weight.is_finite() || *weight < 0.)
+    {
+        return Err(Error::InvalidArgument(
+            "weights must be finite and not negative",
+        ));
+    }
+    if !strategies.iter().any(|(_, weight)| *weight > 0.) {
+        return Err(Error::InvalidArgument(
+            "at least one weight must be more than 0",
+        ));
+    }
+    if strategies
+        .iter()
+        .any(|(strategy, _)| matches!(strategy, Strategy::RankLimited { top_rank: 0 }))
+    {
+        return Err(Error::InvalidArgument("top_rank must be more than 0"));
+    }
+    Ok(())
+}
+
+impl<T, R> MixtureGenerator<'_, T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng,
+{
+    /// The strategies, with their weights.
+    pub fn strategies(&self) -> &[(Strategy, f64)] {
+        &self.strategies
+    }
+
+    /// Generates the next term, with the strategy it was generated with.
+    pub fn next_term_with_strategy(&mut self) -> (Strategy, Vec<T>) {
+        let strategy = choose_by_weight(&mut self.chain.rng, &self.strategies)
+            .expect("checked there's a weight more than 0");
+        let max_lookbehind = self.max_lookbehind;
+        let chain = &mut *self.chain;
+        let term = match strategy {
+            Strategy::Sample => chain.generate_max_look(max_lookbehind),
+            Strategy::Greedy { max_len, tie_break } => {
+                chain.generate_greedy(max_lookbehind, max_len, tie_break)
+            }
+            Strategy::RankLimited { top_rank } => {
+                chain.generate_rank_limited(max_lookbehind, top_rank)
+            }
+            Strategy::Confident { min_count, max_len } => {
+                chain.generate_confident(max_lookbehind, min_count, max_len)
+            }
+            Strategy::Smooth => chain.generate_smooth(max_lookbehind),
+            Strategy::AuthenticStart => chain.generate_authentic_start(max_lookbehind),
+        };
+        (strategy, term)
+    }
+
+    /// Generates the next term.
+    pub fn next_term(&mut self) -> Vec<T> {
+        self.next_term_with_strategy().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    fn chain() -> MarkovChain<char, XorShiftRng> {
+        let mut mc = MarkovChain::new_with_rng(2, XorShiftRng::from_seed([5; 16]));
+        for word in ["brun", "brunda", "kelso", "tam", "tamsin"] {
+            mc.train(word.chars());
+        }
+        mc
+    }
+
+    #[test]
+    fn mixture1() {
+        let greedy = Strategy::Greedy {
+            max_len: 10,
+            tie_break: TieBreak::First,
+        };
+        let strategies = vec![
+            (Strategy::Sample, 0.7),
+            (greedy, 0.2),
+            (Strategy::RankLimited { top_rank: 1 }, 0.1),
+            (Strategy::Smooth, 0.),
+        ];
+
+        let mut mc = chain();
+        let mut mixture = mc.mixture(2, strategies.clone());
+        assert_eq!(mixture.strategies(), strategies.as_slice());
+        let terms: Vec<(Strategy, Vec<char>)> = (0..200)
+            .map(|_| mixture.next_term_with_strategy())
+            .collect();
+        let count = |strategy: Strategy| terms.iter().filter(|(s, _)| *s == strategy).count();
+        assert!(count(Strategy::Sample) > 110);
+        assert!(count(greedy) > 20);
+        assert_eq!(count(Strategy::Smooth), 0);
+        let greedy_term = chain().generate_greedy(2, 10, TieBreak::First);
+        assert!(terms
+            .iter()
+            .filter(|(s, _)| *s == greedy)
+            .all(|(_, term)| *term == greedy_term));
+
+        // The same seed gives the same terms
+        let mut mc = chain();
+        let mut mixture = mc.mixture(2, strategies);
+        assert!(terms
+            .iter()
+            .all(|term| *term == mixture.next_term_with_strategy()));
+    }
+
+    #[test]
+    fn try_mixture1() {
+        let mut mc = chain();
+        assert!(mc.try_mixture(2, vec![(Strategy::Sample, 1.)]).is_ok());
+        assert!(mc.try_mixture(3, vec![(Strategy::Sample, 1.)]).is_err());
+        assert!(mc.try_mixture(2, vec![]).is_err());
+        assert!(mc.try_mixture(2, vec![(Strategy::Sample, 0.)]).is_err());
+        assert!(mc
+            .try_mixture(2, vec![(Strategy::Sample, 1.), (Strategy::Smooth, -1.)])
+            .is_err());
+        assert!(mc
+            .try_mixture(2, vec![(Strategy::Sample, f64::NAN)])
+            .is_err());
+        let no_rank = Strategy::RankLimited { top_rank: 0 };
+        assert!(mc.try_mixture(2, vec![(no_rank, 1.)]).is_err());
+        let mut untrained: MarkovChain<char, _> = MarkovChain::new(2);
+        assert!(untrained
+            .try_mixture(2, vec![(Strategy::Sample, 1.)])
+            .is_err());
+    }
+}
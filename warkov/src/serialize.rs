@@ -0,0 +1,179 @@
+//! Saving and loading chains with serde.
+
+use super::{Boundary, Error, MarkovChain, RoundingMode, StageMap, StartMode};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+// Maps are saved as lists of pairs, since formats like JSON only allow strings as map keys.
+
+/// The version of the saved layout which this version of warkov writes. It goes up when the
+/// layout changes, so chains saved by a newer version give an error rather than being misread.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(serde::Serialize)]
+struct ChainRef<'a, T> {
+    version: u32,
+    size: usize,
+    stages: Vec<(&'a [Boundary<T>], usize, Vec<(&'a Boundary<T>, &'a usize)>)>,
+    alphabet: (usize, Vec<(&'a T, &'a usize)>),
+    lengths: Vec<(&'a usize, &'a usize)>,
+    members: Vec<(&'a T, Vec<(&'a T, &'a usize)>)>,
+    start_mode: StartMode,
+    rounding: RoundingMode,
+}
+
+#[derive(serde::Deserialize)]
+struct ChainData<T> {
+    // Chains saved before there was a version have the layout of version 1
+    #[serde(default = "first_version")]
+    version: u32,
+    size: usize,
+    stages: Vec<(Vec<Boundary<T>>, usize, Vec<(Boundary<T>, usize)>)>,
+    alphabet: (usize, Vec<(T, usize)>),
+    lengths: Vec<(usize, usize)>,
+    members: Vec<(T, Vec<(T, usize)>)>,
+    start_mode: StartMode,
+    #[serde(default)]
+    rounding: RoundingMode,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// Saves everything except the random number generator. The stages are saved sorted, so the
+/// same chain is always saved the same way.
+impl<T, R> Serialize for MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut stages: Vec<_> = self
+            .stages
+            .iter()
+            .map(|(stage, stats)| (stage.as_slice(), stats.0, stats.1.iter().collect()))
+            .collect();
+        stages.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        ChainRef {
+            version: FORMAT_VERSION,
+            size: self.size,
+            stages,
+            alphabet: (self.alphabet.0, self.alphabet.1.iter().collect()),
+            lengths: self.lengths.iter().collect(),
+            members: self
+                .members
+                .iter()
+                .map(|(class, items)| (class, items.iter().collect()))
+                .collect(),
+            start_mode: self.start_mode,
+            rounding: self.rounding,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Loads a chain without a random number generator, which can be given one with `with_rng`.
+/// Returns an error if the chain was saved by a newer version of warkov, which wraps an
+/// `Error::UnsupportedVersion`, if the lookbehind is 0, or if the chain fails `validate`.
+impl<'de, T> Deserialize<'de> for MarkovChain<T, ()>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let data = ChainData::deserialize(deserializer)?;
+        if data.version == 0 || data.version > FORMAT_VERSION {
+            return Err(de::Error::custom(Error::UnsupportedVersion {
+                version: data.version,
+                supported: FORMAT_VERSION,
+            }));
+        }
+        if data.size == 0 {
+            return Err(de::Error::custom("the lookbehind must be more than 0"));
+        }
+        let mut stages = StageMap::new();
+        for (stage, total, next) in data.stages {
+            stages.insert(stage, (total, next.into_iter().collect()));
+        }
+        let chain = MarkovChain {
+            size: data.size,
+            rng: (),
+            stages,
+            alphabet: (data.alphabet.0, data.alphabet.1.into_iter().collect()),
+            lengths: data.lengths.into_iter().collect(),
+            members: data
+                .members
+                .into_iter()
+                .map(|(class, items)| (class, items.into_iter().collect()))
+                .collect(),
+            start_mode: data.start_mode,
+            rounding: data.rounding,
+            cumulative: StageMap::new(),
+            openings: BTreeMap::new(),
+        };
+        chain.validate().map_err(de::Error::custom)?;
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn serde_round_trip1() {
+        let mut mc = MarkovChain::new_with_rng(3, XorShiftRng::from_seed([5; 16]));
+        for word in ["brun", "brunda", "kelso", "tam", "tamsin"] {
+            mc.train(word.chars());
+        }
+        mc.set_start_mode(StartMode::Openings);
+        let json = serde_json::to_string(&mc).unwrap();
+        assert_eq!(json, serde_json::to_string(&mc).unwrap());
+
+        let loaded: MarkovChain<char, ()> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.size, 3);
+        assert_eq!(loaded.stages, mc.stages);
+        assert_eq!(loaded.alphabet, mc.alphabet);
+        assert_eq!(loaded.lengths, mc.lengths);
+        assert_eq!(loaded.start_mode, StartMode::Openings);
+
+        // The same seed gives the same terms from the loaded chain
+        let mut loaded = loaded.with_rng(XorShiftRng::from_seed([5; 16]));
+        for _ in 0..20 {
+            assert_eq!(mc.generate(), loaded.generate());
+        }
+
+        let inconsistent = json.replacen("\"size\":3", "\"size\":1", 1);
+        assert!(serde_json::from_str::<MarkovChain<char, ()>>(&inconsistent).is_err());
+        let no_size = json.replacen("\"size\":3", "\"size\":0", 1);
+        assert!(serde_json::from_str::<MarkovChain<char, ()>>(&no_size).is_err());
+    }
+
+    #[test]
+    fn serde_versions() {
+        let mut mc = MarkovChain::without_rng(2);
+        mc.train("abc".chars());
+        let json = serde_json::to_string(&mc).unwrap();
+        assert!(json.starts_with("{\"version\":1,"));
+
+        // Before there was a version
+        let unversioned = json.replacen("\"version\":1,", "", 1);
+        let loaded: MarkovChain<char, ()> = serde_json::from_str(&unversioned).unwrap();
+        assert_eq!(loaded.stages, mc.stages);
+
+        let newer = json.replacen("\"version\":1", "\"version\":2", 1);
+        let err = serde_json::from_str::<MarkovChain<char, ()>>(&newer).err();
+        assert_eq!(
+            err.unwrap().to_string(),
+            Error::UnsupportedVersion {
+                version: 2,
+                supported: 1
+            }
+            .to_string()
+        );
+    }
+}
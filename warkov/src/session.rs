@@ -0,0 +1,141 @@
+//! Generating many terms in a row.
+
+use super::{
+    alphabet_counts, choose_opening_from, weighted_choice_counts, Boundary, Counts, MarkovChain,
+    Openings, Result, StartMode,
+};
+use rand::Rng;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Generates terms from a chain one after another, reusing the same buffers for each term.
+///
+/// Made with [`MarkovChain::session`]. The terms are the same as calling `generate_max_look`
+/// with the same lookbehind the same number of times, but without allocating the stage again
+/// for every term, which adds up when generating millions of terms. Terms start as the
+/// `StartMode` of the chain says, like `generate_max_look`. With `StartMode::Openings`, the
+/// openings are only found once, when the session starts.
+pub struct GenerationSession<'a, T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    chain: &'a mut MarkovChain<T, R>,
+    max_lookbehind: usize,
+    curr: Vec<Boundary<T>>,
+    alphabet: Counts<T>,
+    openings: Option<Openings<T>>,
+}
+
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng,
+{
+    /// Starts generating many terms with a custom lookbehind, using the random number generator
+    /// of this chain.
+    /// # Panics
+    /// If `max_lookbehind` is 0 or greater than the lookbehind this was created with.
+    pub fn session(&mut self, max_lookbehind: usize) -> GenerationSession<'_, T, R> {
+        assert!(max_lookbehind >= 1 && max_lookbehind <= self.size);
+        let alphabet = alphabet_counts(&self.alphabet);
+        let openings = match self.start_mode {
+            StartMode::Incremental => None,
+            StartMode::Openings => Some(self.openings(max_lookbehind)),
+        };
+        GenerationSession {
+            chain: self,
+            max_lookbehind,
+            curr: Vec::with_capacity(max_lookbehind + 1),
+            alphabet,
+            openings,
+        }
+    }
+
+    /// Like `session`, but returns an error if `max_lookbehind` is invalid, or this chain hasn't
+    /// been trained.
+    pub fn try_session(&mut self, max_lookbehind: usize) -> Result<GenerationSession<'_, T, R>> {
+        self.check_can_generate(max_lookbehind)?;
+        Ok(self.session(max_lookbehind))
+    }
+}
+
+impl<T, R> GenerationSession<'_, T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    R: Rng,
+{
+    /// Generates the next term.
+    pub fn next_term(&mut self) -> Vec<T> {
+        let MarkovChain { stages, rng, .. } = &mut *self.chain;
+        let mut term = Vec::new();
+        self.curr.clear();
+        match &self.openings {
+            None => self.curr.push(Boundary::Start),
+            Some(openings) => {
+                self.curr.extend(choose_opening_from(rng, openings));
+                term.extend(self.curr.iter().filter_map(|item| match item {
+                    Boundary::Tok(t) => Some(t.clone()),
+                    _ => None,
+                }));
+            }
+        }
+
+        loop {
+            // Fall back to shorter stages, and then the alphabet, like `choose_next_by`
+            let next = loop {
+                if let Some(stats) = stages.get(&self.curr) {
+                    break weighted_choice_counts(rng, stats);
+                }
+                if self.curr.len() == 1 {
+                    break weighted_choice_counts(rng, &self.alphabet);
+                }
+                self.curr.remove(0);
+            };
+
+            match next {
+                Boundary::End => return term,
+                Boundary::Start => unreachable!("the start is never recorded as a next item"),
+                Boundary::Tok(t) => {
+                    self.curr.push(Boundary::Tok(t.clone()));
+                    if self.curr.len() > self.max_lookbehind {
+                        self.curr.remove(0);
+                    }
+                    term.push(t);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn session_matches_generate() {
+        for start_mode in [StartMode::Incremental, StartMode::Openings] {
+            let chain = || {
+                let mut mc = MarkovChain::new_with_rng(3, XorShiftRng::from_seed([3; 16]));
+                for word in ["abc", "bbc", "acb", "rust", "trust", "crust", "bust", ""] {
+                    mc.train(word.chars());
+                }
+                mc.set_start_mode(start_mode);
+                mc
+            };
+
+            for max_lookbehind in 1..=3 {
+                let mut mc = chain();
+                let expected: Vec<Vec<char>> = (0..50)
+                    .map(|_| mc.generate_max_look(max_lookbehind))
+                    .collect();
+
+                let mut mc = chain();
+                let mut session = mc.session(max_lookbehind);
+                let terms: Vec<Vec<char>> = (0..50).map(|_| session.next_term()).collect();
+                assert_eq!(terms, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! A summary of what a chain has learnt.
+
+use super::{Boundary, MarkovChain};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// A summary of a chain, from [`MarkovChain::stats`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ChainStats {
+    /// The lookbehind the chain was created with.
+    pub order: usize,
+    /// The number of stages, of every length.
+    pub num_contexts: usize,
+    /// The number of different items seen in training.
+    pub alphabet_size: usize,
+    /// The number of items seen in training, counting repeats.
+    pub total_tokens: usize,
+    /// The number of terms trained on.
+    pub num_terms: usize,
+    /// The mean number of different things seen after a stage, see
+    /// [`MarkovChain::average_branching`].
+    pub avg_branching: f64,
+    /// The length of the longest term which can be generated with the lookbehind the chain was
+    /// created with, or `None` if there's no limit, because the chain can repeat itself forever.
+    pub max_term_len: Option<usize>,
+}
+
+impl<T, R> MarkovChain<T, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    /// A summary of this chain.
+    ///
+    /// Working out `max_term_len` looks at every stage which generating can reach, so this takes
+    /// longer for bigger chains.
+    pub fn stats(&self) -> ChainStats {
+        ChainStats {
+            order: self.size,
+            num_contexts: self.stages.len(),
+            alphabet_size: self.alphabet_size(),
+            total_tokens: self.alphabet.0,
+            num_terms: self
+                .stages
+                .get([Boundary::Start].as_slice())
+                .map_or(0, |stats| stats.0),
+            avg_branching: self.average_branching(),
+            max_term_len: self.max_term_len(),
+        }
+    }
+
+    /// The number of items in the longest path through the stages, see `ChainStats`. This is
+    /// a depth first search, without recursion, since terms can be very long.
+    fn max_term_len(&self) -> Option<usize> {
+        let after = |curr: &Vec<Boundary<T>>| -> Vec<Vec<Boundary<T>>> {
+            let mut curr = curr.clone();
+            let counts = self.next_counts(&mut curr);
+            counts
+                .1
+                .keys()
+                .filter(|next| matches!(next, Boundary::Tok(_)))
+                .map(|next| {
+                    let mut after = curr.clone();
+                    after.push(next.clone());
+                    if after.len() > self.size {
+                        after.remove(0);
+                    }
+                    after
+                })
+                .collect()
+        };
+
+        let mut longest: BTreeMap<Vec<Boundary<T>>, usize> = BTreeMap::new();
+        let mut in_progress: BTreeSet<Vec<Boundary<T>>> = BTreeSet::new();
+        let start = vec![Boundary::Start];
+        in_progress.insert(start.clone());
+        // Each stage being searched, the stages after it which are left, and the longest path
+        // after it so far.
+        let mut todo = vec![(after(&start), start, 0)];
+        while let Some((left, _, max)) = todo.last_mut() {
+            if let Some(next) = left.pop() {
+                if let Some(len) = longest.get(&next) {
+                    *max = (*max).max(len + 1);
+                } else if in_progress.contains(&next) {
+                    return None;
+                } else {
+                    in_progress.insert(next.clone());
+                    todo.push((after(&next), next, 0));
+                }
+            } else {
+                let (_, curr, max) = todo.pop().expect("checked above");
+                in_progress.remove(&curr);
+                longest.insert(curr, max);
+                match todo.last_mut() {
+                    Some((_, _, parent_max)) => *parent_max = (*parent_max).max(max + 1),
+                    None => return Some(max),
+                }
+            }
+        }
+
+        unreachable!("the start is always searched last")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats1() {
+        let mut mc = MarkovChain::without_rng(2);
+        for word in ["abc", "ab", "b"] {
+            mc.train(word.chars());
+        }
+        let stats = mc.stats();
+        assert_eq!(stats.order, 2);
+        assert_eq!(stats.num_contexts, mc.stages.len());
+        assert_eq!(stats.alphabet_size, 3);
+        assert_eq!(stats.total_tokens, 6);
+        assert_eq!(stats.num_terms, 3);
+        assert_eq!(stats.avg_branching, mc.average_branching());
+        assert_eq!(stats.max_term_len, Some(3));
+
+        // "aa" can be followed by another "a" forever
+        mc.train("aaa".chars());
+        assert_eq!(mc.stats().max_term_len, None);
+
+        let empty: MarkovChain<char, ()> = MarkovChain::without_rng(3);
+        assert_eq!(empty.stats().num_terms, 0);
+        assert_eq!(empty.stats().max_term_len, Some(0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn stats_serialize() {
+        fn is_serialize<S: serde::Serialize>(_: &S) {}
+        let mc: MarkovChain<char, ()> = MarkovChain::without_rng(1);
+        is_serialize(&mc.stats());
+    }
+}
@@ -0,0 +1,149 @@
+//! Chains which remember where their training came from.
+
+use super::{Boundary, Error, MarkovChain, Result};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// A chain trained on several sources, e.g. word lists in different languages, which also keeps
+/// the counts from each source, so `source_fraction` can say how much of a transition came from
+/// each one.
+///
+/// This keeps a separate chain for each source, as well as the combined chain, so it takes
+/// more memory than training a `MarkovChain` on every source.
+pub struct TaggedChain<T, S, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+{
+    chain: MarkovChain<T, R>,
+    sources: BTreeMap<S, MarkovChain<T, ()>>,
+}
+
+impl<T, S, R> TaggedChain<T, S, R>
+where
+    T: Hash + Eq + Clone + Default + Ord + Debug,
+    S: Ord + Clone,
+{
+    /// Keeps track of the sources of everything `chain` is trained on from now on. What it has
+    /// already been trained on isn't from any source.
+    pub fn new(chain: MarkovChain<T, R>) -> Self {
+        TaggedChain {
+            chain,
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// The combined chain, trained on every source.
+    pub fn chain(&self) -> &MarkovChain<T, R> {
+        &self.chain
+    }
+
+    /// The combined chain, e.g. to generate from it. Anything it's trained on directly isn't
+    /// from any source, so it doesn't change `source_fraction`.
+    pub fn chain_mut(&mut self) -> &mut MarkovChain<T, R> {
+        &mut self.chain
+    }
+
+    /// The combined chain, without the counts from each source.
+    pub fn into_chain(self) -> MarkovChain<T, R> {
+        self.chain
+    }
+
+    /// The sources this has been trained on, sorted.
+    pub fn sources(&self) -> impl Iterator<Item = &S> + '_ {
+        self.sources.keys()
+    }
+
+    /// Trains the chain on `term`, which came from `source`.
+    pub fn train(&mut self, source: S, term: impl Iterator<Item = T> + Clone) {
+        let size = self.chain.size;
+        self.sources
+            .entry(source)
+            .or_insert_with(|| MarkovChain::without_rng(size))
+            .train(term.clone());
+        self.chain.train(term);
+    }
+
+    /// Adds the counts of `other`, which was trained on `source`, to the chain, like
+    /// `MarkovChain::merge`.
+    ///
+    /// # Panics
+    /// If the chains have different lookbehinds.
+    pub fn merge_tagged<R2>(&mut self, source: S, other: &MarkovChain<T, R2>) {
+        assert_eq!(
+            self.chain.size, other.size,
+            "can only merge chains with the same lookbehind"
+        );
+        let size = self.chain.size;
+        self.sources
+            .entry(source)
+            .or_insert_with(|| MarkovChain::without_rng(size))
+            .merge(other);
+        self.chain.merge(other);
+    }
+
+    /// Like `merge_tagged`, but returns an error, without changing anything, if the chains have
+    /// different lookbehinds.
+    pub fn try_merge_tagged<R2>(&mut self, source: S, other: &MarkovChain<T, R2>) -> Result<()> {
+        if self.chain.size != other.size {
+            return Err(Error::SizeMismatch {
+                size: self.chain.size,
+                other_size: other.size,
+            });
+        }
+        self.merge_tagged(source, other);
+        Ok(())
+    }
+
+    /// How much of the times `next` was seen after the stage `context` came from `source`,
+    /// between 0 and 1, or NaN if no source has seen `next` after `context`.
+    pub fn source_fraction(&self, context: &[Boundary<T>], next: &Boundary<T>, source: &S) -> f64 {
+        let count = |chain: &MarkovChain<T, ()>| {
+            chain
+                .stages
+                .get(context)
+                .and_then(|stats| stats.1.get(next))
+                .copied()
+                .unwrap_or(0)
+        };
+        let total: usize = self.sources.values().map(count).sum();
+        let from_source = self.sources.get(source).map_or(0, count);
+        from_source as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_fraction1() {
+        let mut tagged = TaggedChain::new(MarkovChain::without_rng(1));
+        tagged.train("en", "cat".chars());
+        tagged.train("en", "can".chars());
+        tagged.train("sco", "cam".chars());
+        tagged.train("sco", "ken".chars());
+
+        let c = [Boundary::Tok('c')];
+        let a = Boundary::Tok('a');
+        assert!((tagged.source_fraction(&c, &a, &"en") - 2. / 3.).abs() < 1e-9);
+        assert!((tagged.source_fraction(&c, &a, &"sco") - 1. / 3.).abs() < 1e-9);
+        assert_eq!(tagged.source_fraction(&c, &a, &"fr"), 0.);
+        assert!(tagged.source_fraction(&c, &Boundary::End, &"en").is_nan());
+        assert_eq!(tagged.sources().collect::<Vec<_>>(), vec![&"en", &"sco"]);
+
+        let mut other = MarkovChain::without_rng(1);
+        other.train("cab".chars());
+        tagged.merge_tagged("sco", &other);
+        assert_eq!(tagged.source_fraction(&c, &a, &"en"), 0.5);
+        assert_eq!(tagged.chain().stages[c.as_slice()].1[&a], 4);
+
+        // Training the chain directly isn't from any source
+        tagged.chain_mut().train("ca".chars());
+        assert_eq!(tagged.source_fraction(&c, &a, &"en"), 0.5);
+        assert!(tagged
+            .try_merge_tagged("en", &MarkovChain::<char, ()>::without_rng(2))
+            .is_err());
+        assert_eq!(tagged.into_chain().stages[c.as_slice()].1[&a], 5);
+    }
+}